@@ -1,50 +1,813 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs};
+use std::{cmp::Reverse, collections::HashMap, fs, sync::Mutex};
 
-use crate::utils::compare_path;
-use toml;
+use crate::delay::Delay;
+use crate::matcher::RequestMatch;
+use crate::route::{HostPattern, PathPattern, RouteTrie};
+use crate::scenario::Scenarios;
+use crate::template::Template;
+use crate::utils::SimpleRNG;
+use hteapot::HttpRequest;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Endpoint {
     pub path: String,
-    pub status: u16,
+    pub host: Option<String>,
+    pub status: Status,
+    #[serde(default)]
     pub body: String,
+    pub variants: Option<Vec<Variant>>,
+    pub responses: Option<Vec<Response>>,
+    #[serde(default)]
+    pub sequence: Sequence,
+    pub scenario: Option<String>,
+    pub required_state: Option<String>,
+    pub new_state: Option<String>,
+    pub delay: Option<Delay>,
+    pub seed: Option<u64>,
+    pub priority: Option<i32>,
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(rename = "match")]
+    pub request_match: Option<RequestMatch>,
+    #[serde(skip)]
+    pub compiled: Compiled,
+}
+
+// `status = 201` or a template like `status = "{{#if arg.id}}200{{else}}404{{/if}}"`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Status {
+    Code(u16),
+    Template(String),
+}
+
+// One representation of the response, chosen by the Accept header
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Variant {
+    pub content_type: String,
+    pub body: String,
+}
+
+// One reply of a `responses` sequence, missing fields come from the endpoint
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Response {
+    pub status: Option<Status>,
+    pub body: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+// Which of the `responses` is served on each call
+//   last    in order, then the last one forever (default)
+//   cycle   in order, starting over after the last one
+//   random  any of them, using the endpoint's random stream
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sequence {
+    #[default]
+    Last,
+    Cycle,
+    Random,
+}
+
+impl Sequence {
+    // Response for the call number `call`, counting from 0
+    pub fn index(&self, call: usize, len: usize, rng: &mut SimpleRNG) -> usize {
+        match self {
+            Sequence::Last => call.min(len - 1),
+            Sequence::Cycle => call % len,
+            Sequence::Random => rng.next_range(0, len as u64) as usize,
+        }
+    }
+}
+
+// Compiled entry of `responses`
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub code: Option<u16>,
+    pub status: Option<Template>,
+    pub body: Option<Body>,
+    pub headers: Vec<(String, Template)>,
+}
+
+// Templates of an endpoint, compiled once by Config::import
+#[derive(Clone, Debug, Default)]
+pub struct Compiled {
+    // unique per endpoint, for the call counters
+    pub key: String,
+    pub path: PathPattern,
+    pub host: Option<HostPattern>,
+    pub body: Body,
+    // content type and body of each variant, in config order
+    pub variants: Vec<(String, Body)>,
+    pub status: Option<Template>,
+    pub headers: Vec<(String, Template)>,
+    pub responses: Vec<Step>,
+}
+
+fn compile_headers(
+    headers: &Option<HashMap<String, String>>,
+) -> Result<Vec<(String, Template)>, String> {
+    let mut compiled = Vec::new();
+    for (name, value) in headers.iter().flatten() {
+        compiled.push((name.clone(), Template::parse(value)?));
+    }
+    Ok(compiled)
+}
+
+#[derive(Clone, Debug)]
+pub enum Body {
+    Static { text: String, is_json: bool },
+    Template(Template),
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::Static {
+            text: String::new(),
+            is_json: false,
+        }
+    }
+}
+
+impl Body {
+    pub fn compile(body: &str) -> Result<Self, String> {
+        let template = Template::parse(body)?;
+        Ok(match template.static_text() {
+            Some(text) => Body::Static {
+                is_json: serde_json::from_str::<serde_json::Value>(&text).is_ok(),
+                text,
+            },
+            None => Body::Template(template),
+        })
+    }
+}
+
+impl Endpoint {
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.compiled.path = PathPattern::parse(&self.path)?;
+        self.compiled.host = self.host.as_deref().map(HostPattern::new);
+        self.compiled.body = Body::compile(&self.body)?;
+        self.compiled.variants = Vec::new();
+        for variant in self.variants.iter().flatten() {
+            self.compiled
+                .variants
+                .push((variant.content_type.clone(), Body::compile(&variant.body)?));
+        }
+        self.compiled.status = match &self.status {
+            Status::Code(_) => None,
+            Status::Template(status) => Some(Template::parse(status)?),
+        };
+        self.compiled.headers = compile_headers(&self.headers)?;
+        self.compiled.responses = Vec::new();
+        for response in self.responses.iter().flatten() {
+            let (code, status) = match &response.status {
+                Some(Status::Code(code)) => (Some(*code), None),
+                Some(Status::Template(status)) => (None, Some(Template::parse(status)?)),
+                None => (None, None),
+            };
+            let body = response.body.as_deref().map(Body::compile).transpose()?;
+            self.compiled.responses.push(Step {
+                code,
+                status,
+                body,
+                headers: compile_headers(&response.headers)?,
+            });
+        }
+        if let Some(request_match) = self.request_match.as_mut() {
+            request_match.compile()?;
+        }
+        if self.scenario.is_none() && (self.required_state.is_some() || self.new_state.is_some()) {
+            return Err("required_state and new_state need a scenario".to_string());
+        }
+        if let Some(delay) = &self.delay {
+            delay.validate()?;
+        }
+        Ok(())
+    }
+
+    // Higher priority first, then endpoints scoped to a host, then the more
+    // specific path, then endpoints with match criteria or a required state.
+    // Anything left keeps the order of the config file.
+    fn rank(&self) -> (Reverse<i32>, bool, Vec<u8>, bool) {
+        (
+            Reverse(self.priority.unwrap_or(0)),
+            self.host.is_none(),
+            self.compiled.path.specificity(),
+            self.request_match.is_none() && self.required_state.is_none(),
+        )
+    }
+
+    fn serves_host(&self, req: &HttpRequest) -> bool {
+        self.compiled
+            .host
+            .as_ref()
+            .is_none_or(|h| h.is_match(req.headers.get("Host").map(|v| v.as_str())))
+    }
+
+    // Only endpoints with a required state take the lock
+    fn in_state(&self, scenarios: &Mutex<Scenarios>) -> bool {
+        match (&self.scenario, &self.required_state) {
+            (Some(scenario), Some(required)) => {
+                scenarios.lock().unwrap().state(scenario) == required
+            }
+            _ => true,
+        }
+    }
+
+    // Checks the host, the scenario state and the match criteria, the path
+    // is handled by the router
+    pub fn accepts(&self, req: &HttpRequest, body: &str, scenarios: &Mutex<Scenarios>) -> bool {
+        self.serves_host(req)
+            && self.in_state(scenarios)
+            && self
+                .request_match
+                .as_ref()
+                .is_none_or(|m| m.is_match(req, body))
+    }
+
+    // Endpoints that don't need the request to build their response, apart
+    // from picking a variant
+    pub fn is_static(&self) -> bool {
+        matches!(self.compiled.body, Body::Static { .. })
+            && self
+                .compiled
+                .variants
+                .iter()
+                .all(|(_, body)| matches!(body, Body::Static { .. }))
+            && self.compiled.status.is_none()
+            && self.compiled.headers.is_empty()
+            && self.compiled.responses.is_empty()
+    }
+
+    pub fn static_status(&self) -> u16 {
+        match self.status {
+            Status::Code(code) => code,
+            Status::Template(_) => 200,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DB {
     pub path: String,
+    pub host: Option<String>,
     pub data: String,
+    pub delay: Option<Delay>,
 }
 
-pub trait EndpointSearch {
-    fn get_iter(&self, key: &str) -> Option<Endpoint>;
+// Key of the endpoints that answer any method
+pub const ANY: &str = "ANY";
+
+// Methods listed in Allow for paths served by ANY endpoints
+const ANY_ALLOW: &[&str] = &["DELETE", "GET", "HEAD", "PATCH", "POST", "PUT"];
+
+// Method names are HTTP tokens, e.g. PROPFIND or PURGE
+fn is_method_token(method: &str) -> bool {
+    !method.is_empty()
+        && method
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
 }
 
-impl EndpointSearch for Vec<Endpoint> {
-    fn get_iter(&self, key: &str) -> Option<Endpoint> {
-        for endpoint in self {
-            if compare_path(endpoint.path.to_string(), key.to_string()) {
-                return Some(endpoint.clone());
-            } else {
-                continue;
-            }
+// Value of the Allow header for the methods of a path
+pub fn allow_header(methods: &[&str]) -> String {
+    let mut allow: Vec<&str> = Vec::new();
+    for method in methods {
+        match *method {
+            ANY => allow.extend(ANY_ALLOW),
+            method => allow.push(method),
         }
-        return None;
     }
+    allow.push("OPTIONS");
+    allow.sort();
+    allow.dedup();
+    allow.join(", ")
 }
 
 #[derive(Serialize, Debug, Deserialize)]
 pub struct Config {
     pub endpoints: HashMap<String, Vec<Endpoint>>,
     pub db: Option<Vec<DB>>,
+    pub header_default: Option<String>,
+    #[serde(skip)]
+    pub routes: HashMap<String, RouteTrie>,
 }
 
 impl Config {
-    pub fn import(path: &str) -> Self {
-        let config_toml = fs::read_to_string(path).unwrap();
+    pub fn import(path: &str) -> Result<Self, String> {
+        let config_toml = fs::read_to_string(path).map_err(|e| e.to_string())?;
         // Parsear el TOML
-        let config: Config = toml::from_str(&config_toml).unwrap();
-        return config;
+        let mut config: Config = toml::from_str(&config_toml).map_err(|e| e.to_string())?;
+        config.compile()?;
+        Ok(config)
+    }
+
+    // Compiles every endpoint and builds the router of each method
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.routes.clear();
+        // hteapot uppercases request methods, so `propfind` and `PROPFIND`
+        // are the same key. Keys are merged in sorted order so ties between
+        // them are broken the same way on every run.
+        let mut keys: Vec<(String, Vec<Endpoint>)> = self.endpoints.drain().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut endpoints: HashMap<String, Vec<Endpoint>> = HashMap::new();
+        for (method, list) in keys {
+            if !is_method_token(&method) {
+                return Err(format!("Invalid method '{}'", method));
+            }
+            endpoints
+                .entry(method.to_uppercase())
+                .or_default()
+                .extend(list);
+        }
+        self.endpoints = endpoints;
+        for (method, endpoints) in self.endpoints.iter_mut() {
+            for endpoint in endpoints.iter_mut() {
+                endpoint.compile().map_err(|e| {
+                    format!("Invalid template in {} {}: {}", method, endpoint.path, e)
+                })?;
+            }
+            // stable sort so ties stay in declaration order
+            endpoints.sort_by_cached_key(Endpoint::rank);
+            let trie = self.routes.entry(method.clone()).or_default();
+            for (id, endpoint) in endpoints.iter_mut().enumerate() {
+                endpoint.compiled.key = format!("{} #{} {}", method, id, endpoint.path);
+                trie.insert(&endpoint.compiled.path, id);
+            }
+        }
+        Ok(())
+    }
+
+    // Methods with an endpoint for the path and host, ignoring match criteria
+    pub fn allowed_methods(&self, req: &HttpRequest) -> Vec<&str> {
+        let mut methods: Vec<&str> = self
+            .routes
+            .iter()
+            .filter(|(method, trie)| {
+                let endpoints = &self.endpoints[method.as_str()];
+                trie.lookup(&req.path)
+                    .iter()
+                    .any(|(id, _)| endpoints[*id].serves_host(req))
+            })
+            .map(|(method, _)| method.as_str())
+            .collect();
+        // HEAD is served by the GET endpoints
+        if methods.contains(&"GET") && !methods.contains(&"HEAD") {
+            methods.push("HEAD");
+        }
+        methods.sort();
+        methods
+    }
+
+    // Names of every scenario used by the endpoints
+    pub fn scenario_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .endpoints
+            .values()
+            .flatten()
+            .filter_map(|e| e.scenario.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Best ranked endpoint of the method that accepts the request. Endpoints
+    // of the method itself go before ANY ones, HEAD falls back to GET before
+    // ANY and OPTIONS only uses its own.
+    pub fn route(
+        &self,
+        method: &str,
+        req: &HttpRequest,
+        body: &str,
+        scenarios: &Mutex<Scenarios>,
+    ) -> Option<(&Endpoint, HashMap<String, String>)> {
+        self.route_method(method, req, body, scenarios)
+            .or_else(|| match method {
+                "OPTIONS" => None,
+                "HEAD" => self
+                    .route_method("GET", req, body, scenarios)
+                    .or_else(|| self.route_method(ANY, req, body, scenarios)),
+                _ => self.route_method(ANY, req, body, scenarios),
+            })
+    }
+
+    fn route_method(
+        &self,
+        method: &str,
+        req: &HttpRequest,
+        body: &str,
+        scenarios: &Mutex<Scenarios>,
+    ) -> Option<(&Endpoint, HashMap<String, String>)> {
+        let endpoints = self.endpoints.get(method)?;
+        self.routes
+            .get(method)?
+            .lookup(&req.path)
+            .into_iter()
+            .map(|(id, params)| (&endpoints[id], params))
+            .find(|(endpoint, _)| endpoint.accepts(req, body, scenarios))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Context;
+
+    #[test]
+    fn test_compile_static_body() {
+        match Body::compile("{\"status\": \"up\"}").unwrap() {
+            Body::Static { text, is_json } => {
+                assert_eq!(text, "{\"status\": \"up\"}");
+                assert!(is_json);
+            }
+            Body::Template(_) => panic!("expected a static body"),
+        }
+        assert!(matches!(
+            Body::compile("API is up").unwrap(),
+            Body::Static { is_json: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_compile_template_body() {
+        assert!(matches!(
+            Body::compile("{{path}}").unwrap(),
+            Body::Template(_)
+        ));
+        assert!(Body::compile("{{#if x}}").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_status_and_headers() {
+        let config: Config = toml::from_str(
+            r#"
+            [[endpoints.POST]]
+            path = "/users"
+            status = "{{#if arg.id}}201{{else}}400{{/if}}"
+            body = "ok"
+            headers = { Location = "/users/{{arg.id}}" }
+
+            [[endpoints.GET]]
+            path = "/health"
+            status = 200
+            body = "up"
+            "#,
+        )
+        .unwrap();
+        let mut post = config.endpoints["POST"][0].clone();
+        post.compile().unwrap();
+        assert!(!post.is_static());
+        assert!(post.compiled.status.is_some());
+        assert_eq!(post.compiled.headers.len(), 1);
+        let mut get = config.endpoints["GET"][0].clone();
+        get.compile().unwrap();
+        assert!(get.is_static());
+        assert_eq!(get.static_status(), 200);
+    }
+
+    #[test]
+    fn test_endpoint_variants() {
+        let mut endpoint: Endpoint = toml::from_str(
+            r#"
+            path = "/users"
+            status = 200
+            variants = [
+                { content_type = "application/json", body = '[{"name": "Jane"}]' },
+                { content_type = "text/csv", body = "name\nJane" },
+                { content_type = "text/plain", body = "{{arg.name}}" },
+            ]
+            "#,
+        )
+        .unwrap();
+        endpoint.compile().unwrap();
+        assert_eq!(endpoint.body, "");
+        assert_eq!(endpoint.compiled.variants.len(), 3);
+        assert_eq!(endpoint.compiled.variants[1].0, "text/csv");
+        assert!(!endpoint.is_static());
+    }
+
+    #[test]
+    fn test_endpoint_responses() {
+        let mut endpoint: Endpoint = toml::from_str(
+            r#"
+            path = "/job"
+            status = 200
+            body = "done"
+            sequence = "cycle"
+            responses = [
+                { status = 503, headers = { Retry-After = "1" } },
+                { status = "{{arg.code}}", body = "pending" },
+                { },
+            ]
+            "#,
+        )
+        .unwrap();
+        endpoint.compile().unwrap();
+        assert!(!endpoint.is_static());
+        let steps = &endpoint.compiled.responses;
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].code, Some(503));
+        assert!(steps[0].body.is_none());
+        assert_eq!(steps[0].headers.len(), 1);
+        assert!(steps[1].status.is_some());
+        assert!(steps[2].code.is_none() && steps[2].body.is_none());
+        assert_eq!(endpoint.sequence, Sequence::Cycle);
+    }
+
+    #[test]
+    fn test_sequence_index() {
+        let mut rng = SimpleRNG::with_seed(1);
+        let mut calls = |sequence: Sequence| -> Vec<usize> {
+            (0..5).map(|c| sequence.index(c, 3, &mut rng)).collect()
+        };
+        assert_eq!(calls(Sequence::Last), vec![0, 1, 2, 2, 2]);
+        assert_eq!(calls(Sequence::Cycle), vec![0, 1, 2, 0, 1]);
+        assert!(calls(Sequence::Random).iter().all(|i| *i < 3));
+    }
+
+    #[test]
+    fn test_first_matching_endpoint_wins() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.GET]]
+            path = "/users"
+            status = 200
+            body = "admins"
+            match = { query = { type = "admin" } }
+
+            [[endpoints.GET]]
+            path = "/users"
+            status = 200
+            body = "everyone"
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        let mut req = HttpRequest::new(hteapot::HttpMethod::GET, "/users");
+        assert_eq!(
+            config
+                .route("GET", &req, "", &Mutex::default())
+                .unwrap()
+                .0
+                .body,
+            "everyone"
+        );
+        req.args.insert("type".to_string(), "admin".to_string());
+        assert_eq!(
+            config
+                .route("GET", &req, "", &Mutex::default())
+                .unwrap()
+                .0
+                .body,
+            "admins"
+        );
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.POST]]
+            path = "/users"
+            status = 201
+            body = "created"
+
+            [[endpoints.GET]]
+            path = "/users"
+            status = 200
+            body = "[]"
+
+            [[endpoints.DELETE]]
+            path = "/users/{{id:int}}"
+            status = 204
+            body = ""
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        let allowed = |path: &str| -> Vec<String> {
+            let req = HttpRequest::new(hteapot::HttpMethod::GET, path);
+            config
+                .allowed_methods(&req)
+                .iter()
+                .map(|m| m.to_string())
+                .collect()
+        };
+        assert_eq!(allowed("/users"), vec!["GET", "HEAD", "POST"]);
+        assert_eq!(allowed("/users/1"), vec!["DELETE"]);
+        assert!(allowed("/users/me").is_empty());
+
+        let req = HttpRequest::new(hteapot::HttpMethod::HEAD, "/users");
+        let (endpoint, _) = config.route("HEAD", &req, "", &Mutex::default()).unwrap();
+        assert_eq!(endpoint.body, "[]");
+    }
+
+    #[test]
+    fn test_any_and_custom_methods() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.ANY]]
+            path = "/echo"
+            status = 200
+            body = "{{method}}"
+
+            [[endpoints.GET]]
+            path = "/echo"
+            status = 200
+            body = "get"
+
+            [[endpoints.propfind]]
+            path = "/dav"
+            status = 207
+            body = "<multistatus/>"
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        let body = |method: &str, path: &str| {
+            let req = HttpRequest::new(hteapot::HttpMethod::from_str(method), path);
+            config
+                .route(req.method.to_str(), &req, "", &Mutex::default())
+                .map(|(endpoint, path_args)| {
+                    let ctx = Context::from_request(&req, "", path_args, "");
+                    match &endpoint.compiled.body {
+                        Body::Static { text, .. } => text.clone(),
+                        Body::Template(t) => t.render(&ctx, &mut SimpleRNG::new()),
+                    }
+                })
+        };
+        assert_eq!(body("GET", "/echo").unwrap(), "get");
+        assert_eq!(body("PUT", "/echo").unwrap(), "PUT");
+        assert_eq!(body("PROPFIND", "/dav").unwrap(), "<multistatus/>");
+        assert!(body("OPTIONS", "/echo").is_none());
+        let allow = |path: &str| {
+            let req = HttpRequest::new(hteapot::HttpMethod::GET, path);
+            allow_header(&config.allowed_methods(&req))
+        };
+        assert_eq!(
+            allow("/echo"),
+            "DELETE, GET, HEAD, OPTIONS, PATCH, POST, PUT"
+        );
+        assert_eq!(allow("/dav"), "OPTIONS, PROPFIND");
+
+        let mut config: Config =
+            toml::from_str("[[endpoints.\"GET /\"]]\npath = \"/\"\nstatus = 200\nbody = \"\"")
+                .unwrap();
+        assert!(config.compile().is_err());
+
+        // keys differing only in case merge in sorted order, `GET` first
+        for _ in 0..10 {
+            let mut config: Config = toml::from_str(
+                "[[endpoints.get]]\npath = \"/\"\nstatus = 200\nbody = \"lower\"\n\
+                 [[endpoints.GET]]\npath = \"/\"\nstatus = 200\nbody = \"upper\"",
+            )
+            .unwrap();
+            config.compile().unwrap();
+            let req = HttpRequest::new(hteapot::HttpMethod::GET, "/");
+            let (endpoint, _) = config.route("GET", &req, "", &Mutex::default()).unwrap();
+            assert_eq!(endpoint.body, "upper");
+        }
+    }
+
+    #[test]
+    fn test_host_scoped_endpoints() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.GET]]
+            path = "/{{service}}"
+            status = 200
+            body = "default"
+
+            [[endpoints.GET]]
+            path = "/**"
+            host = "billing.local"
+            status = 200
+            body = "billing"
+
+            [[endpoints.GET]]
+            path = "/health"
+            host = "auth.local"
+            status = 200
+            body = "auth"
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        let body = |host: Option<&str>| {
+            let mut req = HttpRequest::new(hteapot::HttpMethod::GET, "/health");
+            if let Some(host) = host {
+                req.headers.insert("Host", host);
+            }
+            config
+                .route("GET", &req, "", &Mutex::default())
+                .unwrap()
+                .0
+                .body
+                .clone()
+        };
+        assert_eq!(body(Some("auth.local:8080")), "auth");
+        assert_eq!(body(Some("billing.local")), "billing");
+        assert_eq!(body(Some("catalog.local")), "default");
+        assert_eq!(body(None), "default");
+    }
+
+    #[test]
+    fn test_scenario_states() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.GET]]
+            path = "/cart"
+            status = 200
+            body = "empty"
+
+            [[endpoints.GET]]
+            path = "/cart"
+            scenario = "cart"
+            required_state = "Has items"
+            status = 200
+            body = "one item"
+
+            [[endpoints.POST]]
+            path = "/cart"
+            scenario = "cart"
+            new_state = "Has items"
+            status = 201
+            body = ""
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        assert_eq!(config.scenario_names(), vec!["cart"]);
+        let scenarios = Mutex::new(Scenarios::new(config.scenario_names()));
+        let req = HttpRequest::new(hteapot::HttpMethod::GET, "/cart");
+        let body = |scenarios: &Mutex<Scenarios>| {
+            config
+                .route("GET", &req, "", scenarios)
+                .unwrap()
+                .0
+                .body
+                .clone()
+        };
+        assert_eq!(body(&scenarios), "empty");
+        scenarios.lock().unwrap().set("cart", "Has items");
+        assert_eq!(body(&scenarios), "one item");
+
+        let mut endpoint: Endpoint =
+            toml::from_str("path = \"/\"\nstatus = 200\nbody = \"\"\nnew_state = \"x\"").unwrap();
+        assert!(endpoint.compile().is_err());
+    }
+
+    #[test]
+    fn test_most_specific_endpoint_wins() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[endpoints.GET]]
+            path = "/users/**"
+            status = 200
+            body = "subtree"
+
+            [[endpoints.GET]]
+            path = "/users/{{name}}"
+            status = 200
+            body = "name"
+
+            [[endpoints.GET]]
+            path = "/users/{{id:int}}"
+            status = 200
+            body = "id"
+
+            [[endpoints.GET]]
+            path = "/users/me"
+            status = 200
+            body = "me"
+
+            [[endpoints.GET]]
+            path = "/users/{{name}}"
+            status = 200
+            body = "pinned"
+            priority = 1
+            match = { query = { pin = "1" } }
+            "#,
+        )
+        .unwrap();
+        config.compile().unwrap();
+        let body = |path: &str, pin: bool| {
+            let mut req = HttpRequest::new(hteapot::HttpMethod::GET, path);
+            if pin {
+                req.args.insert("pin".to_string(), "1".to_string());
+            }
+            config
+                .route("GET", &req, "", &Mutex::default())
+                .unwrap()
+                .0
+                .body
+                .clone()
+        };
+        assert_eq!(body("/users/me", false), "me");
+        assert_eq!(body("/users/42", false), "id");
+        assert_eq!(body("/users/jane", false), "name");
+        assert_eq!(body("/users/jane/posts", false), "subtree");
+        assert_eq!(body("/users/me", true), "pinned");
     }
 }
@@ -0,0 +1,265 @@
+use crate::utils::civil_from_days;
+
+// Date helpers for {{now}}
+// Everything is computed from the epoch with fixed offsets, there is no
+// timezone database involved.
+//   {{now}}                        epoch seconds
+//   {{now unit="ms"}}              epoch milliseconds
+//   {{now format="rfc3339"}}       2024-05-01T10:00:00Z
+//   {{now format="http"}}          Wed, 01 May 2024 10:00:00 GMT
+//   {{now format="%d/%m/%Y %H:%M"}} strftime-like pattern
+//   {{now offset="+1h30m"}}        shifted time, units: ms s m h d w
+//   {{now tz="+02:00"}}            fixed UTC offset used when formatting
+
+const DAYS: &[&str] = &[
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: &[&str] = &[
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// Parses offsets like "+1h", "-30m" or "1d12h" into milliseconds
+pub fn parse_offset(offset: &str) -> Result<i64, String> {
+    let offset = offset.trim();
+    let (sign, rest) = match offset.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, offset.strip_prefix('+').unwrap_or(offset)),
+    };
+    if rest.is_empty() {
+        return Err(format!("Invalid offset '{}'", offset));
+    }
+    let too_large = || format!("Offset '{}' is too large", offset);
+    let mut total: i64 = 0;
+    let mut number = String::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let amount: i64 = number
+            .parse()
+            .map_err(|_| format!("Invalid offset '{}'", offset))?;
+        number.clear();
+        let unit = match c {
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                1
+            }
+            's' => 1_000,
+            'm' => 60_000,
+            'h' => 3_600_000,
+            'd' => 86_400_000,
+            'w' => 604_800_000,
+            _ => return Err(format!("Invalid offset unit '{}'", c)),
+        };
+        total = add_millis(total, amount, unit).ok_or_else(too_large)?;
+    }
+    if !number.is_empty() {
+        // bare numbers are seconds
+        let amount: i64 = number.parse().map_err(|_| too_large())?;
+        total = add_millis(total, amount, 1_000).ok_or_else(too_large)?;
+    }
+    Ok(sign * total)
+}
+
+// total + amount * unit, None when it doesn't fit in an i64
+fn add_millis(total: i64, amount: i64, unit: i64) -> Option<i64> {
+    amount.checked_mul(unit)?.checked_add(total)
+}
+
+// Parses "+02:00", "-0530" or "Z" into minutes east of UTC
+pub fn parse_tz(tz: &str) -> Result<i64, String> {
+    let tz = tz.trim();
+    if tz.eq_ignore_ascii_case("z") || tz.eq_ignore_ascii_case("utc") {
+        return Ok(0);
+    }
+    let (sign, rest) = match tz.chars().next() {
+        Some('+') => (1, &tz[1..]),
+        Some('-') => (-1, &tz[1..]),
+        _ => return Err(format!("Invalid timezone '{}'", tz)),
+    };
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid timezone '{}'", tz));
+    }
+    let hours: i64 = digits[..2].parse().unwrap_or(0);
+    let minutes: i64 = digits[2..].parse().unwrap_or(0);
+    Ok(sign * (hours * 60 + minutes))
+}
+
+fn tz_suffix(tz_minutes: i64, colon: bool) -> String {
+    let sign = if tz_minutes < 0 { '-' } else { '+' };
+    let abs = tz_minutes.abs();
+    if colon {
+        format!("{}{:02}:{:02}", sign, abs / 60, abs % 60)
+    } else {
+        format!("{}{:02}{:02}", sign, abs / 60, abs % 60)
+    }
+}
+
+// Formats epoch milliseconds with a named format or a strftime-like pattern
+pub fn format(millis: i64, format: &str, tz_minutes: i64) -> String {
+    match format {
+        "rfc3339" | "iso" | "iso8601" => {
+            let zone = if tz_minutes == 0 { "Z" } else { "%:z" };
+            strftime(millis, &format!("%Y-%m-%dT%H:%M:%S{}", zone), tz_minutes)
+        }
+        "rfc3339ms" => {
+            let zone = if tz_minutes == 0 { "Z" } else { "%:z" };
+            strftime(millis, &format!("%Y-%m-%dT%H:%M:%S.%L{}", zone), tz_minutes)
+        }
+        "http" | "rfc7231" => strftime(millis, "%a, %d %b %Y %H:%M:%S GMT", 0),
+        "date" => strftime(millis, "%Y-%m-%d", tz_minutes),
+        "time" => strftime(millis, "%H:%M:%S", tz_minutes),
+        pattern => strftime(millis, pattern, tz_minutes),
+    }
+}
+
+pub fn strftime(millis: i64, pattern: &str, tz_minutes: i64) -> String {
+    let local = millis.saturating_add(tz_minutes * 60_000);
+    let days = local.div_euclid(86_400_000);
+    let day_millis = local.rem_euclid(86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second, milli) = (
+        day_millis / 3_600_000,
+        day_millis / 60_000 % 60,
+        day_millis / 1_000 % 60,
+        day_millis % 1_000,
+    );
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    let year_day = days - days_from_civil(year, 1, 1) + 1;
+
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('y') => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('e') => out.push_str(&format!("{:>2}", day)),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('I') => out.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('L') => out.push_str(&format!("{:03}", milli)),
+            Some('j') => out.push_str(&format!("{:03}", year_day)),
+            Some('a') => out.push_str(&DAYS[weekday][..3]),
+            Some('A') => out.push_str(DAYS[weekday]),
+            Some('b') => out.push_str(&MONTHS[month as usize - 1][..3]),
+            Some('B') => out.push_str(MONTHS[month as usize - 1]),
+            Some('u') => out.push_str(&(if weekday == 0 { 7 } else { weekday }).to_string()),
+            Some('w') => out.push_str(&weekday.to_string()),
+            Some('s') => out.push_str(&millis.div_euclid(1_000).to_string()),
+            Some('F') => out.push_str(&format!("{:04}-{:02}-{:02}", year, month, day)),
+            Some('T') => out.push_str(&format!("{:02}:{:02}:{:02}", hour, minute, second)),
+            Some('z') => out.push_str(&tz_suffix(tz_minutes, false)),
+            Some(':') if chars.clone().next() == Some('z') => {
+                chars.next();
+                out.push_str(&tz_suffix(tz_minutes, true));
+            }
+            Some('Z') => out.push_str(if tz_minutes == 0 { "UTC" } else { "" }),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+// Inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-29T13:05:09.042Z, a Thursday
+    const LEAP_DAY: i64 = 1_709_211_909_042;
+
+    #[test]
+    fn test_named_formats() {
+        assert_eq!(format(LEAP_DAY, "rfc3339", 0), "2024-02-29T13:05:09Z");
+        assert_eq!(format(LEAP_DAY, "rfc3339ms", 0), "2024-02-29T13:05:09.042Z");
+        assert_eq!(format(LEAP_DAY, "http", 0), "Thu, 29 Feb 2024 13:05:09 GMT");
+        assert_eq!(
+            format(LEAP_DAY, "rfc3339", 120),
+            "2024-02-29T15:05:09+02:00"
+        );
+        assert_eq!(format(0, "date", -60), "1969-12-31");
+    }
+
+    #[test]
+    fn test_strftime_pattern() {
+        assert_eq!(
+            strftime(LEAP_DAY, "%A %e %B %y, %I:%M %p (day %j) %%", 0),
+            "Thursday 29 February 24, 01:05 PM (day 060) %"
+        );
+        assert_eq!(strftime(LEAP_DAY, "%s.%L %z", 0), "1709211909.042 +0000");
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("+1h"), Ok(3_600_000));
+        assert_eq!(parse_offset("-30m"), Ok(-1_800_000));
+        assert_eq!(parse_offset("1d12h"), Ok(129_600_000));
+        assert_eq!(parse_offset("250ms"), Ok(250));
+        assert_eq!(parse_offset("90"), Ok(90_000));
+        assert!(parse_offset("1y").is_err());
+        assert!(parse_offset("").is_err());
+        assert!(parse_offset("+9223372036854775807w").is_err());
+        assert!(parse_offset("9999999999999999999").is_err());
+        assert!(parse_offset("5000000000000000h5000000000000000h").is_err());
+    }
+
+    #[test]
+    fn test_parse_tz() {
+        assert_eq!(parse_tz("+02:00"), Ok(120));
+        assert_eq!(parse_tz("-0530"), Ok(-330));
+        assert_eq!(parse_tz("Z"), Ok(0));
+        assert!(parse_tz("Europe/Madrid").is_err());
+    }
+
+    #[test]
+    fn test_days_from_civil_roundtrip() {
+        for days in [-1000, 0, 11016, 19782, 50000] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+}
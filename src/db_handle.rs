@@ -1,3 +1,6 @@
+use crate::delay::Delay;
+use crate::route::HostPattern;
+use crate::utils::parse_body;
 use hteapot::HttpStatus;
 use serde_json::Value;
 use std::collections::HashMap;
@@ -26,8 +29,13 @@ use std::collections::HashMap;
 //  },
 // ]
 
+// Methods answered by every db, for the Allow header
+pub const ALLOW: &str = "GET, POST, PATCH, DELETE, OPTIONS";
+
 pub struct DbHandle {
     pub root_path: String,
+    pub host: Option<HostPattern>,
+    pub delay: Option<Delay>,
     db_data: Value,
 }
 
@@ -45,7 +53,12 @@ impl DbHandle {
         }
         let db_data: Value = db_data.unwrap();
 
-        Ok(DbHandle { root_path, db_data })
+        Ok(DbHandle {
+            root_path,
+            host: None,
+            delay: None,
+            db_data,
+        })
     }
     fn split_path(path: &str) -> Option<(&str, &str)> {
         let mut parts = path.rsplitn(2, '/');
@@ -63,7 +76,7 @@ impl DbHandle {
             status: HttpStatus::BadRequest,
             text: "Can't remove all the db",
         })?;
-        let pointer = self.db_data.pointer_mut(&parent).ok_or(HttpErr {
+        let pointer = self.db_data.pointer_mut(parent).ok_or(HttpErr {
             status: HttpStatus::NotFound,
             text: "Parent not found",
         })?;
@@ -89,7 +102,7 @@ impl DbHandle {
                 .remove(attr);
         }
         let result = pointer.to_string();
-        return Ok(result);
+        Ok(result)
     }
 
     fn patch(
@@ -157,11 +170,10 @@ impl DbHandle {
                 status: HttpStatus::BadRequest,
                 text: "Invalid Path",
             })?;
-            let r = list.push(body.ok_or(HttpErr {
+            list.push(body.ok_or(HttpErr {
                 status: HttpStatus::BadRequest,
                 text: "Invalid Body",
             })?);
-            let _ = serde_json::to_string(&r.clone());
         } else {
             let body_c = body.clone().ok_or(HttpErr {
                 status: HttpStatus::BadRequest,
@@ -206,10 +218,10 @@ impl DbHandle {
         if pointer.is_array() {
             let mut array: Vec<Value> = pointer.as_array().unwrap().clone();
             for (k, v) in args {
-                let k: &str = k.as_str().as_ref();
+                let k: &str = k.as_str();
                 array = array
                     .into_iter()
-                    .filter(|i| i[k].to_string() == v)
+                    .filter(|i| format!("{}", i[k]) == v)
                     .collect::<Vec<Value>>();
             }
             pointer = Value::Array(array);
@@ -233,8 +245,10 @@ impl DbHandle {
         }
     }
 
-    pub fn is_match(&self, path: &String) -> bool {
+    // Dbs scoped to a host only serve requests for that host
+    pub fn is_match(&self, path: &str, host: Option<&str>) -> bool {
         path.starts_with(self.root_path.as_str())
+            && self.host.as_ref().is_none_or(|h| h.is_match(host))
     }
 
     pub fn process(
@@ -267,12 +281,7 @@ impl DbHandle {
         } else {
             path
         };
-        let body = serde_json::from_str::<Value>(&body);
-        let body = if body.is_err() {
-            None
-        } else {
-            Some(body.unwrap())
-        };
+        let body = parse_body(&body);
         match method {
             "GET" => self.get(path, args),
             "POST" => self.post(path, args, body),
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utils::SimpleRNG;
+
+// Simulated latency before a response is sent, in milliseconds
+//   delay = 250                                              fixed
+//   delay = [100, 500]                                       uniform range
+//   delay = { distribution = "normal", mean = 200, stddev = 50 }
+//   delay = { distribution = "lognormal", median = 200, sigma = 0.5 }
+// The same forms are accepted by --delay as "250", "100-500",
+// "normal:200:50" and "lognormal:200:0.5".
+
+// Longest delay that is configured or sampled, so a heavy tail can't hold a
+// worker for good
+pub const MAX_DELAY: u64 = 300_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Delay {
+    Fixed(u64),
+    Range([u64; 2]),
+    Distribution(Distribution),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum Distribution {
+    Normal { mean: f64, stddev: f64 },
+    Lognormal { median: f64, sigma: f64 },
+}
+
+impl Delay {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid delay '{}'", src);
+        let number = |n: &str| n.trim().parse::<f64>().map_err(|_| invalid());
+        let delay = match src.split(':').collect::<Vec<&str>>().as_slice() {
+            ["normal", mean, stddev] => Delay::Distribution(Distribution::Normal {
+                mean: number(mean)?,
+                stddev: number(stddev)?,
+            }),
+            ["lognormal", median, sigma] => Delay::Distribution(Distribution::Lognormal {
+                median: number(median)?,
+                sigma: number(sigma)?,
+            }),
+            [fixed] => match fixed.split_once('-') {
+                Some((min, max)) => Delay::Range([
+                    min.trim().parse().map_err(|_| invalid())?,
+                    max.trim().parse().map_err(|_| invalid())?,
+                ]),
+                None => Delay::Fixed(fixed.trim().parse().map_err(|_| invalid())?),
+            },
+            _ => return Err(invalid()),
+        };
+        delay.validate()?;
+        Ok(delay)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let too_long = || format!("Delays can't be longer than {} ms", MAX_DELAY);
+        // NaN and infinite values are outside of every range
+        let within = |n: &f64, max: f64| (0.0..=max).contains(n);
+        match self {
+            Delay::Fixed(millis) if *millis > MAX_DELAY => Err(too_long()),
+            Delay::Fixed(_) => Ok(()),
+            Delay::Range([min, max]) if min > max => {
+                Err(format!("Invalid delay range {}-{}", min, max))
+            }
+            Delay::Range([_, max]) if *max > MAX_DELAY => Err(too_long()),
+            Delay::Range(_) => Ok(()),
+            Delay::Distribution(Distribution::Normal { mean, stddev }) => {
+                if !within(mean, MAX_DELAY as f64) || !within(stddev, MAX_DELAY as f64) {
+                    return Err(format!(
+                        "Normal delay needs a mean and stddev between 0 and {} ms",
+                        MAX_DELAY
+                    ));
+                }
+                Ok(())
+            }
+            Delay::Distribution(Distribution::Lognormal { median, sigma }) => {
+                // sigma is the spread of ln(delay), past 10 the tail is all cap
+                if *median <= 0.0 || !within(median, MAX_DELAY as f64) || !within(sigma, 10.0) {
+                    return Err(format!(
+                        "Lognormal delay needs a median between 0 and {} ms and a sigma between 0 and 10",
+                        MAX_DELAY
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Milliseconds to wait, between 0 and MAX_DELAY
+    pub fn sample(&self, rng: &mut SimpleRNG) -> u64 {
+        let millis = match self {
+            Delay::Fixed(millis) => return (*millis).min(MAX_DELAY),
+            Delay::Range([min, max]) => {
+                return rng.next_range(*min, max.saturating_add(1)).min(MAX_DELAY)
+            }
+            Delay::Distribution(Distribution::Normal { mean, stddev }) => {
+                mean + stddev * standard_normal(rng)
+            }
+            Delay::Distribution(Distribution::Lognormal { median, sigma }) => {
+                median * (sigma * standard_normal(rng)).exp()
+            }
+        };
+        millis.clamp(0.0, MAX_DELAY as f64).round() as u64
+    }
+}
+
+// Box-Muller transform
+fn standard_normal(rng: &mut SimpleRNG) -> f64 {
+    let u1 = 1.0 - rng.next_f64();
+    let u2 = rng.next_f64();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Wrapper {
+        delay: Delay,
+    }
+
+    fn from_toml(src: &str) -> Delay {
+        toml::from_str::<Wrapper>(src).unwrap().delay
+    }
+
+    #[test]
+    fn test_config_forms() {
+        assert_eq!(from_toml("delay = 250"), Delay::Fixed(250));
+        assert_eq!(from_toml("delay = [100, 500]"), Delay::Range([100, 500]));
+        assert_eq!(
+            from_toml("delay = { distribution = \"normal\", mean = 200, stddev = 50 }"),
+            Delay::Distribution(Distribution::Normal {
+                mean: 200.0,
+                stddev: 50.0
+            })
+        );
+        assert_eq!(
+            from_toml("delay = { distribution = \"lognormal\", median = 200, sigma = 0.5 }"),
+            Delay::Distribution(Distribution::Lognormal {
+                median: 200.0,
+                sigma: 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_cli() {
+        assert_eq!(Delay::parse("250"), Ok(Delay::Fixed(250)));
+        assert_eq!(Delay::parse("100-500"), Ok(Delay::Range([100, 500])));
+        assert_eq!(
+            Delay::parse("lognormal:200:0.5"),
+            Ok(Delay::Distribution(Distribution::Lognormal {
+                median: 200.0,
+                sigma: 0.5
+            }))
+        );
+        assert!(Delay::parse("500-100").is_err());
+        assert!(Delay::parse("fast").is_err());
+        assert!(Delay::parse("normal:200").is_err());
+    }
+
+    #[test]
+    fn test_validate_bounds() {
+        assert!(Delay::parse("normal:NaN:10").is_err());
+        assert!(Delay::parse("normal:200:inf").is_err());
+        assert!(Delay::parse("lognormal:200:NaN").is_err());
+        assert!(Delay::parse("lognormal:200:50").is_err());
+        assert!(Delay::parse("999999999").is_err());
+        assert!(Delay::parse("0-18446744073709551615").is_err());
+        assert!(Delay::parse("300000").is_ok());
+    }
+
+    #[test]
+    fn test_sample_is_capped() {
+        let mut rng = SimpleRNG::with_seed(5);
+        // not validated, as if built by hand
+        assert_eq!(Delay::Fixed(u64::MAX).sample(&mut rng), MAX_DELAY);
+        assert!(Delay::Range([0, u64::MAX]).sample(&mut rng) <= MAX_DELAY);
+        let heavy = Delay::Distribution(Distribution::Lognormal {
+            median: 1000.0,
+            sigma: 1e6,
+        });
+        assert!((0..100).all(|_| heavy.sample(&mut rng) <= MAX_DELAY));
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut rng = SimpleRNG::with_seed(3);
+        assert_eq!(Delay::Fixed(40).sample(&mut rng), 40);
+        for _ in 0..100 {
+            let millis = Delay::Range([10, 20]).sample(&mut rng);
+            assert!((10..=20).contains(&millis));
+        }
+        let normal = Delay::parse("normal:200:50").unwrap();
+        let samples: Vec<u64> = (0..1000).map(|_| normal.sample(&mut rng)).collect();
+        let mean = samples.iter().sum::<u64>() as f64 / samples.len() as f64;
+        assert!((180.0..220.0).contains(&mean));
+        let lognormal = Delay::parse("lognormal:100:1").unwrap();
+        assert!((0..100).all(|_| lognormal.sample(&mut rng) < 100_000));
+    }
+
+    #[test]
+    fn test_sample_is_reproducible() {
+        let delay = Delay::parse("normal:200:50").unwrap();
+        let mut a = SimpleRNG::with_seed(9);
+        let mut b = SimpleRNG::with_seed(9);
+        assert_eq!(delay.sample(&mut a), delay.sample(&mut b));
+    }
+}
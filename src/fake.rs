@@ -0,0 +1,288 @@
+use serde_json::Value;
+
+use crate::utils::{civil_from_days, SimpleRNG};
+
+// Fake data generators exposed to templates as {{uuid}} and {{fake.<kind>}}
+// All values come from the request RNG so they follow the same seeding rules
+// as {{rand}}.
+
+const FIRST_NAMES: &[&str] = &[
+    "Alberto", "Eithne", "Juan", "Sarah", "James", "Maria", "Liam", "Olivia", "Noah", "Emma",
+    "Lucas", "Sofia", "Hugo", "Lucia", "Mateo", "Chloe", "Ethan", "Aiko", "Omar", "Fatima", "Ivan",
+    "Elena", "Pierre", "Amelie", "Kenji", "Priya", "Diego", "Ingrid", "Tom", "Nora",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Smith", "Ruiz", "Flor", "Perez", "Brown", "Garcia", "Johnson", "Martinez", "Williams",
+    "Lopez", "Jones", "Gonzalez", "Miller", "Davis", "Moreau", "Rossi", "Schmidt", "Tanaka",
+    "Kowalski", "Novak", "Silva", "Andersen", "Murphy", "Khan", "Nguyen", "Fischer",
+];
+
+const DOMAINS: &[&str] = &[
+    "example.com",
+    "example.org",
+    "example.net",
+    "mail.test",
+    "cafetera.dev",
+];
+
+const STREETS: &[&str] = &[
+    "Main Street",
+    "Oak Avenue",
+    "Maple Road",
+    "Calle Mayor",
+    "High Street",
+    "Park Lane",
+    "Elm Street",
+    "Gran Via",
+    "Station Road",
+    "Church Street",
+];
+
+const CITIES: &[&str] = &[
+    "Madrid", "London", "Paris", "Berlin", "Lisbon", "Dublin", "Rome", "Tokyo", "Toronto",
+    "Sydney", "Austin", "Seville", "Oslo", "Prague", "Vienna",
+];
+
+const COUNTRIES: &[&str] = &[
+    "Spain",
+    "United Kingdom",
+    "France",
+    "Germany",
+    "Portugal",
+    "Ireland",
+    "Italy",
+    "Japan",
+    "Canada",
+    "Australia",
+    "United States",
+    "Norway",
+];
+
+const COMPANIES: &[&str] = &[
+    "Acme",
+    "Globex",
+    "Initech",
+    "Umbrella",
+    "Hooli",
+    "Stark",
+    "Wayne",
+    "Tyrell",
+    "Soylent",
+    "Cyberdyne",
+];
+
+const COMPANY_SUFFIXES: &[&str] = &["Inc", "LLC", "Ltd", "S.L.", "GmbH", "Group"];
+
+const LOREM: &[&str] = &[
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "et",
+    "dolore",
+    "magna",
+    "aliqua",
+    "enim",
+    "ad",
+    "minim",
+    "veniam",
+    "quis",
+    "nostrud",
+    "exercitation",
+    "ullamco",
+    "laboris",
+    "nisi",
+    "aliquip",
+    "ex",
+    "ea",
+    "commodo",
+    "consequat",
+];
+
+fn pick<'a>(rng: &mut SimpleRNG, items: &[&'a str]) -> &'a str {
+    items[rng.next_range(0, items.len() as u64) as usize]
+}
+
+fn count_arg(args: &[Value], default: u64) -> u64 {
+    args.first()
+        .and_then(|v| match v {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        })
+        .unwrap_or(default)
+        .max(1)
+}
+
+pub fn uuid(rng: &mut SimpleRNG) -> String {
+    let mut bytes = [0u8; 16];
+    for byte in bytes.iter_mut() {
+        *byte = (rng.next() & 0xff) as u8;
+    }
+    // version 4, RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+pub fn hex(rng: &mut SimpleRNG, len: u64) -> String {
+    (0..len)
+        .map(|_| format!("{:x}", rng.next_range(0, 16)))
+        .collect()
+}
+
+fn words(rng: &mut SimpleRNG, count: u64) -> String {
+    (0..count)
+        .map(|_| pick(rng, LOREM))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn sentence(rng: &mut SimpleRNG) -> String {
+    let count = rng.next_range(6, 13);
+    let mut sentence = words(rng, count);
+    if let Some(first) = sentence.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    sentence.push('.');
+    sentence
+}
+
+fn paragraph(rng: &mut SimpleRNG, sentences: u64) -> String {
+    (0..sentences)
+        .map(|_| sentence(rng))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn date(rng: &mut SimpleRNG) -> (i64, u32, u32) {
+    // any day between 1970-01-01 and 2037-12-31
+    civil_from_days(rng.next_range(0, 24837) as i64)
+}
+
+// Generates the value for {{fake.<kind>}}, None for unknown kinds
+pub fn generate(kind: &str, args: &[Value], rng: &mut SimpleRNG) -> Option<Value> {
+    let value = match kind {
+        "first_name" => pick(rng, FIRST_NAMES).to_string(),
+        "last_name" => pick(rng, LAST_NAMES).to_string(),
+        "name" => format!("{} {}", pick(rng, FIRST_NAMES), pick(rng, LAST_NAMES)),
+        "username" => format!(
+            "{}{}",
+            pick(rng, FIRST_NAMES).to_lowercase(),
+            rng.next_range(1, 1000)
+        ),
+        "email" => format!(
+            "{}.{}@{}",
+            pick(rng, FIRST_NAMES).to_lowercase(),
+            pick(rng, LAST_NAMES).to_lowercase(),
+            pick(rng, DOMAINS)
+        ),
+        "phone" => format!(
+            "+1-{:03}-{:03}-{:04}",
+            rng.next_range(200, 1000),
+            rng.next_range(0, 1000),
+            rng.next_range(0, 10000)
+        ),
+        "street" => format!("{} {}", rng.next_range(1, 300), pick(rng, STREETS)),
+        "city" => pick(rng, CITIES).to_string(),
+        "country" => pick(rng, COUNTRIES).to_string(),
+        "zip" => format!("{:05}", rng.next_range(1000, 100000)),
+        "address" => format!(
+            "{} {}, {:05} {}, {}",
+            rng.next_range(1, 300),
+            pick(rng, STREETS),
+            rng.next_range(1000, 100000),
+            pick(rng, CITIES),
+            pick(rng, COUNTRIES)
+        ),
+        "company" => format!("{} {}", pick(rng, COMPANIES), pick(rng, COMPANY_SUFFIXES)),
+        "word" => pick(rng, LOREM).to_string(),
+        "words" | "lorem" => words(rng, count_arg(args, 5)),
+        "sentence" => sentence(rng),
+        "paragraph" => paragraph(rng, count_arg(args, 3)),
+        "date" => {
+            let (y, m, d) = date(rng);
+            format!("{:04}-{:02}-{:02}", y, m, d)
+        }
+        "datetime" => {
+            let (y, m, d) = date(rng);
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                y,
+                m,
+                d,
+                rng.next_range(0, 24),
+                rng.next_range(0, 60),
+                rng.next_range(0, 60)
+            )
+        }
+        "ip" | "ipv4" => format!(
+            "{}.{}.{}.{}",
+            rng.next_range(1, 255),
+            rng.next_range(0, 256),
+            rng.next_range(0, 256),
+            rng.next_range(1, 255)
+        ),
+        "ipv6" => (0..8)
+            .map(|_| format!("{:x}", rng.next_range(0, 0x10000)))
+            .collect::<Vec<String>>()
+            .join(":"),
+        "hex" | "token" => hex(rng, count_arg(args, 32)),
+        "bool" => return Some(Value::Bool(rng.next_range(0, 2) == 1)),
+        _ => return None,
+    };
+    Some(Value::String(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_format() {
+        let id = uuid(&mut SimpleRNG::new());
+        let parts: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            parts.iter().map(|p| p.len()).collect::<Vec<usize>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(parts[2].starts_with('4'));
+        assert!(matches!(&parts[3][..1], "8" | "9" | "a" | "b"));
+    }
+
+    #[test]
+    fn test_generators() {
+        let mut rng = SimpleRNG::new();
+        let email = generate("email", &[], &mut rng).unwrap();
+        assert!(email.as_str().unwrap().contains('@'));
+        let ip = generate("ip", &[], &mut rng).unwrap();
+        assert_eq!(ip.as_str().unwrap().split('.').count(), 4);
+        let token = generate("hex", &[Value::from(8)], &mut rng).unwrap();
+        assert_eq!(token.as_str().unwrap().len(), 8);
+        let lorem = generate("lorem", &[Value::from(4)], &mut rng).unwrap();
+        assert_eq!(lorem.as_str().unwrap().split(' ').count(), 4);
+        let date = generate("date", &[], &mut rng).unwrap();
+        assert_eq!(date.as_str().unwrap().len(), 10);
+        assert!(generate("unknown", &[], &mut rng).is_none());
+    }
+}
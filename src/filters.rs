@@ -0,0 +1,121 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::template::{json_escape, stringify};
+
+// Filters applied with a pipe inside template tags, e.g.
+//   {{arg.name | upper}}
+//   {{body | base64}}
+//   {{header.x-user | default "anon" | lower}}
+
+const FILTERS: &[&str] = &[
+    "upper",
+    "lower",
+    "trim",
+    "base64",
+    "base64decode",
+    "urlencode",
+    "json",
+    "json_escape",
+    "json-escape",
+    "sha256",
+    "default",
+];
+
+pub fn exists(name: &str) -> bool {
+    FILTERS.contains(&name)
+}
+
+// Filters whose output can go inside a JSON string as it is
+pub fn escapes_json(name: &str) -> bool {
+    matches!(name, "json_escape" | "json-escape")
+}
+
+pub fn apply(name: &str, value: Value, args: &[Value]) -> Value {
+    let text = || stringify(&value);
+    match name {
+        "upper" => Value::String(text().to_uppercase()),
+        "lower" => Value::String(text().to_lowercase()),
+        "trim" => Value::String(text().trim().to_string()),
+        "base64" => Value::String(STANDARD.encode(text())),
+        "base64decode" => STANDARD
+            .decode(text().trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .map(Value::String)
+            .unwrap_or(Value::Null),
+        "urlencode" => Value::String(url_encode(&text())),
+        "json" => Value::String(value.to_string()),
+        "json_escape" | "json-escape" => Value::String(json_escape(&text())),
+        "sha256" => {
+            let digest = Sha256::digest(text().as_bytes());
+            Value::String(digest.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        "default" => {
+            let is_empty = matches!(&value, Value::String(s) if s.is_empty());
+            if value.is_null() || is_empty {
+                args.first().cloned().unwrap_or(Value::Null)
+            } else {
+                value
+            }
+        }
+        _ => value,
+    }
+}
+
+// Percent-encodes everything but the RFC 3986 unreserved characters
+pub fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply_str(name: &str, input: &str) -> String {
+        stringify(&apply(name, json!(input), &[]))
+    }
+
+    #[test]
+    fn test_string_filters() {
+        assert_eq!(apply_str("upper", "jane"), "JANE");
+        assert_eq!(apply_str("lower", "JaNe"), "jane");
+        assert_eq!(apply_str("trim", "  x "), "x");
+        assert_eq!(apply_str("base64", "user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(apply_str("base64decode", "dXNlcjpwYXNz"), "user:pass");
+        assert_eq!(apply_str("urlencode", "a b/ñ"), "a%20b%2F%C3%B1");
+        assert_eq!(
+            apply_str("json_escape", "say \"hi\"\n"),
+            "say \\\"hi\\\"\\n"
+        );
+        assert_eq!(
+            apply_str("sha256", "abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let anon = [json!("anon")];
+        assert_eq!(apply("default", Value::Null, &anon), json!("anon"));
+        assert_eq!(apply("default", json!(""), &anon), json!("anon"));
+        assert_eq!(apply("default", json!("bob"), &anon), json!("bob"));
+    }
+
+    #[test]
+    fn test_json_filter() {
+        assert_eq!(apply("json", json!({"a": [1]}), &[]), json!("{\"a\":[1]}"));
+        assert_eq!(apply("json", json!("x"), &[]), json!("\"x\""));
+    }
+}
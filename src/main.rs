@@ -1,18 +1,146 @@
 mod config_parser;
+mod datetime;
 mod db_handle;
+mod delay;
+mod fake;
+mod filters;
+mod matcher;
+mod negotiation;
+mod route;
+mod scenario;
+mod template;
 mod utils;
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use config_parser::{Config, EndpointSearch};
+use config_parser::{allow_header, Body, Config, Endpoint, ANY};
 use db_handle::DbHandle;
-use hteapot::{headers, Hteapot, HttpMethod, HttpResponse, HttpStatus};
+use delay::Delay;
+use hteapot::{headers, Hteapot, HttpHeaders, HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use scenario::Scenarios;
 use serde_json::Value;
-use utils::{clean_arg, print_args, SimpleRNG};
-
-use crate::utils::now;
+use template::Context;
+use utils::{decode_args, percent_decode, print_args, RngPool, SimpleRNG};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Requests served at the same time, a delayed response holds one of them
+const DEFAULT_THREADS: u16 = 4;
+
+// Shared between requests, next to the dbs
+struct State {
+    rngs: Mutex<RngPool>,
+    // calls served by each endpoint with `responses`, by Compiled::key
+    calls: Mutex<HashMap<String, usize>>,
+    scenarios: Mutex<Scenarios>,
+    header_default: String,
+}
+
+// Simulated latency, sampled from the stream of the response so a delay
+// doesn't shift the values other responses get under --seed. The sleep
+// keeps one of the server threads busy.
+fn wait(delay: Option<&Delay>, rng: &mut SimpleRNG) {
+    if let Some(delay) = delay {
+        let millis = delay.sample(rng);
+        std::thread::sleep(Duration::from_millis(millis));
+    }
+}
+
+fn set_content_type(headers: &mut HttpHeaders, content_type: Option<&str>, is_json: bool) {
+    match content_type {
+        Some(content_type) => headers.insert("Content-Type", content_type),
+        None if is_json => headers.insert("Content-Type", "application/json"),
+        None => {}
+    }
+}
+
+// Err is a response that doesn't serve the endpoint, like a 406 when no
+// variant fits the Accept header
+fn endpoint_response(
+    endpoint: &Endpoint,
+    path_args: HashMap<String, String>,
+    req: &HttpRequest,
+    body_text: &str,
+    state: &State,
+    rng: &mut SimpleRNG,
+    mut headers: HttpHeaders,
+) -> Result<Box<HttpResponse>, Box<HttpResponse>> {
+    let compiled = &endpoint.compiled;
+    // With variants the Accept header picks the body and its content type
+    let (body, content_type) = if compiled.variants.is_empty() {
+        (&compiled.body, None)
+    } else {
+        let accept = req.headers.get("Accept").map(|v| v.as_str());
+        let offered: Vec<&str> = compiled
+            .variants
+            .iter()
+            .map(|(ct, _)| ct.as_str())
+            .collect();
+        headers.insert("Vary", "Accept");
+        match negotiation::choose(accept, &offered) {
+            Some(i) => (&compiled.variants[i].1, Some(offered[i])),
+            None => {
+                return Err(HttpResponse::new(
+                    HttpStatus::NotAcceptable,
+                    "Not Acceptable",
+                    Some(headers),
+                ))
+            }
+        }
+    };
+    if endpoint.is_static() {
+        if let Body::Static { text, is_json } = body {
+            set_content_type(&mut headers, content_type, *is_json);
+            let status = HttpStatus::from_u16(endpoint.static_status()).unwrap_or(HttpStatus::OK);
+            return Ok(HttpResponse::new(status, text.as_bytes(), Some(headers)));
+        }
+    }
+
+    let ctx = Context::from_request(req, body_text, path_args, &state.header_default);
+
+    let step = match compiled.responses.len() {
+        0 => None,
+        len => {
+            let mut calls = state.calls.lock().unwrap();
+            let call = calls.entry(compiled.key.clone()).or_insert(0);
+            let index = endpoint.sequence.index(*call, len, rng);
+            *call += 1;
+            Some(&compiled.responses[index])
+        }
+    };
+    // A status of the current response wins over the one of the endpoint
+    let status_template = step
+        .and_then(|s| s.status.as_ref())
+        .or(compiled.status.as_ref());
+    let status = match (step.and_then(|s| s.code), status_template) {
+        (Some(code), _) => code,
+        (None, Some(template)) => template.render(&ctx, rng).trim().parse().unwrap_or(200),
+        (None, None) => endpoint.static_status(),
+    };
+    let status = HttpStatus::from_u16(status).unwrap_or(HttpStatus::OK);
+    let (body, content_type) = match step.and_then(|s| s.body.as_ref()) {
+        Some(body) => (body, None),
+        None => (body, content_type),
+    };
+    let (body, is_json) = match body {
+        Body::Static { text, is_json } => (Cow::Borrowed(text.as_str()), *is_json),
+        Body::Template(template) => {
+            let body = template.render(&ctx, rng);
+            let is_json = serde_json::from_str::<Value>(&body).is_ok();
+            (Cow::Owned(body), is_json)
+        }
+    };
+    set_content_type(&mut headers, content_type, is_json);
+    // Headers from the config win over the defaults, and the ones of the
+    // current response over those of the endpoint
+    let step_headers = step.iter().flat_map(|s| &s.headers);
+    for (name, template) in compiled.headers.iter().chain(step_headers) {
+        headers.insert(name, &template.render(&ctx, rng));
+    }
+    Ok(HttpResponse::new(status, body.as_bytes(), Some(headers)))
+}
 
 fn main() {
     let args = std::env::args().collect::<Vec<String>>();
@@ -24,39 +152,91 @@ fn main() {
         }
     }
     if args.len() < 3 {
-        println!("Usage: {} <port> <config>", args[0]);
+        println!(
+            "Usage: {} <port> <config> [-s] [--seed <n>] [--delay <delay>] [--threads <n>]",
+            args[0]
+        );
         return;
     }
     let addr: String = String::from("0.0.0.0");
     let port: u16 = args[1].clone().parse().unwrap_or(8080);
-    let config = Config::import(&args[2]);
-    let silent = args
-        .get(3)
-        .is_some()
-        .then(|| args.get(3).unwrap().eq("-s"))
-        .is_some();
+    let config = match Config::import(&args[2]) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Error loading config: {}", err);
+            return;
+        }
+    };
+    let options = &args[3..];
+    let silent = options.iter().any(|o| o == "-s");
+    let seed = match options.iter().position(|o| o == "--seed") {
+        Some(i) => match options.get(i + 1).map(|s| s.parse::<u64>()) {
+            Some(Ok(seed)) => Some(seed),
+            _ => {
+                println!("--seed expects a number");
+                return;
+            }
+        },
+        None => None,
+    };
+    let default_delay = match options.iter().position(|o| o == "--delay") {
+        Some(i) => match options.get(i + 1).map(|s| Delay::parse(s)) {
+            Some(Ok(delay)) => Some(delay),
+            Some(Err(err)) => {
+                println!("{}", err);
+                return;
+            }
+            None => {
+                println!("--delay expects a delay like 200, 100-500 or normal:200:50");
+                return;
+            }
+        },
+        None => None,
+    };
+    let threads = match options.iter().position(|o| o == "--threads") {
+        Some(i) => match options.get(i + 1).map(|s| s.parse::<u16>()) {
+            Some(Ok(threads)) if threads > 0 => threads,
+            _ => {
+                println!("--threads expects a number greater than 0");
+                return;
+            }
+        },
+        None => DEFAULT_THREADS,
+    };
     let mut dbs: Vec<db_handle::DbHandle> = Vec::new();
     for method in config.endpoints.keys() {
         for endpoint in config.endpoints[method].iter() {
             println!("Loaded {} {}", method, endpoint.path)
         }
     }
-    if config.db.is_some() {
-        let config_db = config.db.unwrap().clone();
+    if let Some(config_db) = config.db.clone() {
         for db in config_db {
-            let dbh = db_handle::DbHandle::new(db.path, db.data);
-            if dbh.is_err() {
-                println!("Error loading db: {:?}", dbh.err());
+            let mut dbh = match db_handle::DbHandle::new(db.path, db.data) {
+                Ok(dbh) => dbh,
+                Err(err) => {
+                    println!("Error loading db: {:?}", err);
+                    continue;
+                }
+            };
+            if let Some(Err(err)) = db.delay.as_ref().map(Delay::validate) {
+                println!("Error loading db: {}", err);
                 continue;
             }
-            let dbh = dbh.unwrap();
+            dbh.host = db.host.as_deref().map(route::HostPattern::new);
+            dbh.delay = db.delay;
             println!("Loaded {} as db", dbh.root_path);
             dbs.push(dbh);
         }
     }
     let dbs: Arc<Mutex<Vec<DbHandle>>> = Arc::new(Mutex::new(dbs));
     let dbsc = dbs.clone();
-    let teapot = Hteapot::new(&addr, port);
+    let state = Arc::new(State {
+        rngs: Mutex::new(RngPool::new(seed)),
+        calls: Mutex::new(HashMap::new()),
+        scenarios: Mutex::new(Scenarios::new(config.scenario_names())),
+        header_default: config.header_default.clone().unwrap_or_default(),
+    });
+    let teapot = Hteapot::new_threaded(&addr, port, threads);
     println!("Listening on http://{}:{}", addr, port);
     teapot.listen(move|req| {
             let body_text = req.text().unwrap_or(String::new());
@@ -81,66 +261,84 @@ fn main() {
             let star = "*";
             let origin = req.headers.get_owned("Origin").unwrap_or(star.to_string());
             let request_headers = req.headers.get_owned("access-control-request-headers").unwrap_or(star.to_string());
-            let mut headers = headers!("Allow" => "GET, POST, OPTIONS, HEAD", "Access-Control-Allow-Origin" => &origin, "Access-Control-Allow-Headers" => &request_headers ).unwrap();
-
-            if req.method == HttpMethod::OPTIONS {
+            let mut headers = headers!("Access-Control-Allow-Origin" => &origin, "Access-Control-Allow-Headers" => &request_headers ).unwrap();
 
-                return HttpResponse::new(HttpStatus::NoContent, "",  Some(headers));
+            if Scenarios::is_match(&req.path) {
+                let result = state.scenarios.lock().unwrap().process(req.method.to_str(), &req.path);
+                headers.insert("Content-Type", "application/json");
+                return match result {
+                    Ok(r) => HttpResponse::new(HttpStatus::OK, r, Some(headers)),
+                    Err(err) => HttpResponse::new(err.status, err.text, None),
+                };
             }
 
-
-
             {
                 let mut dbs = dbsc.lock().unwrap();
-                let dbh = dbs.iter_mut().find(|dbh| dbh.is_match(&req.path));
-                if dbh.is_some() {
-                    let dbh = dbh.unwrap();
-                    let result = dbh.process(req.method.to_str(), req.path, req.args,body_text);
-                    headers.insert("Content-Type", "application/json");
+                // dbs scoped to the host go before unscoped ones
+                let host = req.headers.get("Host").map(|h| h.as_str());
+                let dbh = dbs
+                    .iter_mut()
+                    .filter(|dbh| dbh.is_match(&req.path, host))
+                    .min_by_key(|dbh| dbh.host.is_none());
+                if let Some(dbh) = dbh {
+                    if req.method == HttpMethod::OPTIONS {
+                        headers.insert("Allow", db_handle::ALLOW);
+                        return HttpResponse::new(HttpStatus::NoContent, "", Some(headers));
+                    }
+                    let path = percent_decode(&req.path, false);
+                    let result = dbh.process(req.method.to_str(), path, decode_args(&req.args), body_text);
+                    let delay = dbh.delay.clone().or_else(|| default_delay.clone());
+                    let mut rng = state.rngs.lock().unwrap().fork(&dbh.root_path, None);
+                    drop(dbs);
+                    wait(delay.as_ref(), &mut rng);
                     return match result {
-                        Ok(r) => HttpResponse::new(HttpStatus::OK, r, Some(headers)),
+                        Ok(r) => {
+                            headers.insert("Content-Type", "application/json");
+                            HttpResponse::new(HttpStatus::OK, r, Some(headers))
+                        }
+                        Err(err) if err.status == HttpStatus::MethodNotAllowed => {
+                            headers.insert("Allow", db_handle::ALLOW);
+                            HttpResponse::new(err.status, err.text, Some(headers))
+                        }
                         Err(err) => HttpResponse::new(err.status, err.text ,None )
                         }
                 }
             }
 
-            let response = config.endpoints.get(&req.method.to_str().to_string());
-            match response {
-                Some(response) => {
-                    let config_item = response.get_iter(&req.path);
-                    match config_item {
-                        Some(endpoint) => {
-                            let status = HttpStatus::from_u16(endpoint.status).unwrap_or(HttpStatus::OK);
-                            let mut body = endpoint.body.to_string()
-                            .replace("{{path}}", &req.path)
-                            .replace("{{body}}", &body_text)
-                            .replace("{{rand}}", SimpleRNG::new().next_range(0, 100).to_string().as_str())
-                            .replace("{{now}}", &now().to_string());
-                            for (key, value) in &req.args {
-                                let _body = body.clone();
-                                body = _body.replace(&format!("{{{{arg.{key}}}}}", key=key),  clean_arg(value.to_string()).as_str());
-                            }
-                            let path_args = utils::get_path_args(req.path.clone(), endpoint.path.clone());
-                            if path_args.is_some() {
-                                for (key, value) in path_args.unwrap() {
-                                    let _body = body.clone();
-                                    body = _body.replace(&format!("{{{{{key}}}}}", key=key), &value);
-                                }
-                            }
-                            if  serde_json::from_str::<Value>(&body).is_ok() {
-                                headers.insert("Content-Type", "application/json");
-                            }
-                            return HttpResponse::new(status, &body,Some(headers) );
-                        }
-                        None => {
-                            return HttpResponse::new(HttpStatus::NotFound, "Not Found", None);
-                        }
+            let method = req.method.to_str();
+            if let Some((endpoint, path_args)) = config.route(method, &req, &body_text, &state.scenarios) {
+                // one draw per response, whatever the endpoint does with it
+                let mut rng = state.rngs.lock().unwrap().fork(&endpoint.compiled.key, endpoint.seed);
+                let response = endpoint_response(endpoint, path_args, &req, &body_text, &state, &mut rng, headers);
+                // Only a served endpoint moves its scenario to the new state
+                if response.is_ok() {
+                    if let (Some(scenario), Some(new_state)) = (&endpoint.scenario, &endpoint.new_state) {
+                        state.scenarios.lock().unwrap().set(scenario, new_state);
                     }
                 }
-                None => {
-                    return HttpResponse::new(HttpStatus::NotFound, "Method Not Found", None);
+                let mut response = response.unwrap_or_else(|not_served| not_served);
+                // HEAD keeps the Content-Length of the body it doesn't send
+                if req.method == HttpMethod::HEAD {
+                    response.content.clear();
                 }
+                wait(endpoint.delay.as_ref().or(default_delay.as_ref()), &mut rng);
+                return response;
+            }
+
+            // Either OPTIONS or nothing matched, both need the methods of the path
+            let allowed = config.allowed_methods(&req);
+            headers.insert("Allow", &allow_header(&allowed));
+            let mut response = if req.method == HttpMethod::OPTIONS {
+                HttpResponse::new(HttpStatus::NoContent, "", Some(headers))
+            } else if !allowed.is_empty() && !allowed.contains(&method) && !allowed.contains(&ANY) {
+                HttpResponse::new(HttpStatus::MethodNotAllowed, "Method Not Allowed", Some(headers))
+            } else {
+                HttpResponse::new(HttpStatus::NotFound, "Not Found", None)
+            };
+            if req.method == HttpMethod::HEAD {
+                response.content.clear();
             }
+            response
 
         });
 }
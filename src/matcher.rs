@@ -0,0 +1,213 @@
+use hteapot::HttpRequest;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::utils::{decode_args, parse_body};
+
+// Extra conditions an endpoint can require besides the path
+//
+// [endpoints.GET.match.query]
+// type = "admin"                         exact value
+// [endpoints.GET.match.headers]
+// Accept = { regex = "xml" }             regex search
+// X-Debug = { present = false }          header must be absent
+// [endpoints.POST.match]
+// body = { json = { role = "admin" } }   JSON subset of the body
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RequestMatch {
+    pub query: Option<HashMap<String, Matcher>>,
+    pub headers: Option<HashMap<String, Matcher>>,
+    pub body: Option<Matcher>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Matcher {
+    Exact(String),
+    Rule(Rule),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub equals: Option<String>,
+    pub regex: Option<String>,
+    pub json: Option<Value>,
+    pub present: Option<bool>,
+    #[serde(skip)]
+    compiled_regex: Option<Regex>,
+}
+
+impl RequestMatch {
+    pub fn compile(&mut self) -> Result<(), String> {
+        let matchers = self
+            .query
+            .iter_mut()
+            .chain(self.headers.iter_mut())
+            .flat_map(|m| m.values_mut())
+            .chain(self.body.iter_mut());
+        for matcher in matchers {
+            if let Matcher::Rule(rule) = matcher {
+                if let Some(regex) = &rule.regex {
+                    let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+                    rule.compiled_regex = Some(regex);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_match(&self, req: &HttpRequest, body: &str) -> bool {
+        let args = decode_args(&req.args);
+        let query_ok = self
+            .query
+            .iter()
+            .flatten()
+            .all(|(name, matcher)| matcher.is_match(args.get(name).map(|v| v.as_str())));
+        let headers_ok = self
+            .headers
+            .iter()
+            .flatten()
+            .all(|(name, matcher)| matcher.is_match(req.headers.get(name).map(|v| v.as_str())));
+        let body_ok = self.body.as_ref().is_none_or(|matcher| {
+            let body = if body.is_empty() { None } else { Some(body) };
+            matcher.is_match(body)
+        });
+        query_ok && headers_ok && body_ok
+    }
+}
+
+impl Matcher {
+    pub fn is_match(&self, value: Option<&str>) -> bool {
+        match self {
+            Matcher::Exact(expected) => value == Some(expected.as_str()),
+            Matcher::Rule(rule) => rule.is_match(value),
+        }
+    }
+}
+
+impl Rule {
+    // All the conditions set in the rule must pass
+    fn is_match(&self, value: Option<&str>) -> bool {
+        if let Some(present) = self.present {
+            if value.is_some() != present {
+                return false;
+            }
+        }
+        let Some(value) = value else {
+            return self.equals.is_none() && self.regex.is_none() && self.json.is_none();
+        };
+        if let Some(equals) = &self.equals {
+            if value != equals {
+                return false;
+            }
+        }
+        if let Some(regex) = &self.compiled_regex {
+            if !regex.is_match(value) {
+                return false;
+            }
+        }
+        if let Some(expected) = &self.json {
+            match parse_body(value) {
+                Some(actual) if is_subset(expected, &actual) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+// Objects match when every expected key matches, arrays when every expected
+// item matches some actual item and anything else has to be equal
+pub fn is_subset(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(k, v)| actual.get(k).is_some_and(|a| is_subset(v, a))),
+        (Value::Array(expected), Value::Array(actual)) => expected
+            .iter()
+            .all(|e| actual.iter().any(|a| is_subset(e, a))),
+        _ => expected == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hteapot::HttpMethod;
+    use serde_json::json;
+
+    fn request_match(toml_src: &str) -> RequestMatch {
+        let mut request_match: RequestMatch = toml::from_str(toml_src).unwrap();
+        request_match.compile().unwrap();
+        request_match
+    }
+
+    fn request(args: &[(&str, &str)], headers: &[(&str, &str)]) -> HttpRequest {
+        let mut req = HttpRequest::new(HttpMethod::GET, "/");
+        for (k, v) in args {
+            req.args.insert(k.to_string(), v.to_string());
+        }
+        for (k, v) in headers {
+            req.headers.insert(k, v);
+        }
+        req
+    }
+
+    #[test]
+    fn test_query_exact() {
+        let m = request_match("query = { type = \"admin\" }");
+        assert!(m.is_match(&request(&[("type", "admin")], &[]), ""));
+        assert!(!m.is_match(&request(&[("type", "user")], &[]), ""));
+        assert!(!m.is_match(&request(&[], &[]), ""));
+    }
+
+    #[test]
+    fn test_query_is_decoded() {
+        let m = request_match("query = { \"first name\" = \"José Luis\" }");
+        assert!(m.is_match(&request(&[("first+name", "Jos%C3%A9%20Luis")], &[]), ""));
+    }
+
+    #[test]
+    fn test_headers_regex_and_presence() {
+        let m = request_match(
+            "headers = { Accept = { regex = \"xml\" }, X-Debug = { present = false } }",
+        );
+        assert!(m.is_match(&request(&[], &[("accept", "application/xml")]), ""));
+        assert!(!m.is_match(&request(&[], &[("accept", "application/json")]), ""));
+        assert!(!m.is_match(
+            &request(&[], &[("accept", "text/xml"), ("x-debug", "1")]),
+            ""
+        ));
+    }
+
+    #[test]
+    fn test_body_json_subset() {
+        let m = request_match("body = { json = { user = { role = \"admin\" }, tags = [\"a\"] } }");
+        let req = request(&[], &[]);
+        assert!(m.is_match(
+            &req,
+            r#"{"user": {"role": "admin", "name": "x"}, "tags": ["b", "a"]}"#
+        ));
+        assert!(!m.is_match(&req, r#"{"user": {"role": "user"}, "tags": ["a"]}"#));
+        assert!(!m.is_match(&req, "not json"));
+        assert!(!m.is_match(&req, ""));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let mut m: RequestMatch = toml::from_str("body = { regex = \"(\" }").unwrap();
+        assert!(m.compile().is_err());
+    }
+
+    #[test]
+    fn test_is_subset() {
+        assert!(is_subset(&json!({"a": 1}), &json!({"a": 1, "b": 2})));
+        assert!(!is_subset(&json!({"a": 1, "b": 2}), &json!({"a": 1})));
+        assert!(is_subset(&json!(1), &json!(1)));
+    }
+}
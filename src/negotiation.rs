@@ -0,0 +1,114 @@
+// Picks the response variant for the Accept header of the request
+//   Accept: application/json                   only JSON
+//   Accept: text/*;q=0.5, application/xml      XML preferred, any text is fine
+//   Accept: */*;q=0, text/csv                  CSV or nothing
+// Each offered type takes the q of the most specific range that covers it.
+// The highest q wins and ties go to the variant declared first. Without an
+// Accept header the first variant is used.
+
+struct MediaRange {
+    kind: String,
+    subtype: String,
+    q: f32,
+}
+
+fn parse_accept(accept: &str) -> Vec<MediaRange> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let (kind, subtype) = params.next()?.trim().split_once('/')?;
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some(MediaRange {
+                kind: kind.trim().to_lowercase(),
+                subtype: subtype.trim().to_lowercase(),
+                q,
+            })
+        })
+        .collect()
+}
+
+// "text/plain; charset=utf-8" -> ("text", "plain")
+fn media_type(content_type: &str) -> (String, String) {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let (kind, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    (kind.to_lowercase(), subtype.to_lowercase())
+}
+
+// q the client gives to a content type, 0 when it isn't acceptable
+fn quality(ranges: &[MediaRange], content_type: &str) -> f32 {
+    let (kind, subtype) = media_type(content_type);
+    ranges
+        .iter()
+        .filter_map(|r| {
+            let specificity = match (r.kind.as_str(), r.subtype.as_str()) {
+                ("*", "*") => 0,
+                (k, "*") if k == kind => 1,
+                (k, s) if k == kind && s == subtype => 2,
+                _ => return None,
+            };
+            Some((specificity, r.q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, q)| q)
+}
+
+// Index of the best offered content type, None when none is acceptable
+pub fn choose(accept: Option<&str>, offered: &[&str]) -> Option<usize> {
+    let accept = accept.map(str::trim).filter(|a| !a.is_empty());
+    let Some(accept) = accept else {
+        return (!offered.is_empty()).then_some(0);
+    };
+    let ranges = parse_accept(accept);
+    let mut best: Option<(usize, f32)> = None;
+    for (i, content_type) in offered.iter().enumerate() {
+        let q = quality(&ranges, content_type);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((i, q));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFERED: &[&str] = &[
+        "application/json",
+        "application/xml",
+        "text/csv",
+        "text/plain; charset=utf-8",
+    ];
+
+    #[test]
+    fn test_exact_and_missing_accept() {
+        assert_eq!(choose(Some("text/csv"), OFFERED), Some(2));
+        assert_eq!(choose(Some("Application/XML"), OFFERED), Some(1));
+        assert_eq!(choose(None, OFFERED), Some(0));
+        assert_eq!(choose(Some(""), OFFERED), Some(0));
+        assert_eq!(choose(Some("text/plain"), OFFERED), Some(3));
+    }
+
+    #[test]
+    fn test_q_values() {
+        assert_eq!(
+            choose(Some("application/json;q=0.5, application/xml"), OFFERED),
+            Some(1)
+        );
+        assert_eq!(choose(Some("text/*;q=0.8, */*;q=0.1"), OFFERED), Some(2));
+        // the more specific range decides
+        assert_eq!(choose(Some("text/*, text/csv;q=0"), OFFERED), Some(3));
+        assert_eq!(choose(Some("*/*"), OFFERED), Some(0));
+    }
+
+    #[test]
+    fn test_not_acceptable() {
+        assert_eq!(choose(Some("image/png"), OFFERED), None);
+        assert_eq!(choose(Some("*/*;q=0"), OFFERED), None);
+        assert_eq!(choose(Some("text/csv"), &[]), None);
+    }
+}
@@ -0,0 +1,471 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::template::RESERVED_NAMES;
+use crate::utils::percent_decode;
+
+// Endpoint paths compiled once at load
+//   /users/me             literal segments
+//   /users/{{name}}       any segment, captured as `name`
+//   /users/{{id:int}}     typed segment: int, number, uuid, alpha, alnum, bool
+//   /posts/{{slug:[a-z-]+}} segment that fully matches a regex
+//   /static/*/logo.png    any single segment, not captured
+//   /files/**             any number of trailing segments, captured as `rest`
+//   /files/{{file:**}}    same, captured as `file`
+// Param names that templates already use (path, method, uuid, ...) are refused.
+// Request segments are percent-decoded before matching, so `%2F` stays inside
+// its segment and captures render as "a/b".
+// A segment that doesn't satisfy its constraint makes the whole path fail, so
+// the next endpoint gets a chance.
+// Endpoints are tried from the most specific path down: per segment a literal
+// beats a typed param, which beats an untyped param or `*`, which beats `**`.
+
+#[derive(Clone, Debug, Default)]
+pub struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Param {
+        name: String,
+        constraint: Option<Constraint>,
+    },
+    Wildcard,
+    // Only valid as the last segment
+    CatchAll(String),
+}
+
+#[derive(Clone, Debug)]
+enum Constraint {
+    Int,
+    Number,
+    Uuid,
+    Alpha,
+    Alnum,
+    Bool,
+    Regex(Regex),
+}
+
+impl Constraint {
+    fn parse(src: &str) -> Result<Self, String> {
+        Ok(match src {
+            "int" => Constraint::Int,
+            "number" | "float" => Constraint::Number,
+            "uuid" => Constraint::Uuid,
+            "alpha" => Constraint::Alpha,
+            "alnum" => Constraint::Alnum,
+            "bool" => Constraint::Bool,
+            regex => {
+                let regex = Regex::new(&format!("^(?:{})$", regex))
+                    .map_err(|e| format!("Invalid path regex '{}': {}", regex, e))?;
+                Constraint::Regex(regex)
+            }
+        })
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Constraint::Int => "int",
+            Constraint::Number => "number",
+            Constraint::Uuid => "uuid",
+            Constraint::Alpha => "alpha",
+            Constraint::Alnum => "alnum",
+            Constraint::Bool => "bool",
+            Constraint::Regex(regex) => regex.as_str(),
+        }
+    }
+
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            Constraint::Int => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+            }
+            Constraint::Number => value.parse::<f64>().is_ok_and(|n| n.is_finite()),
+            Constraint::Uuid => is_uuid(value),
+            Constraint::Alpha => !value.is_empty() && value.chars().all(char::is_alphabetic),
+            Constraint::Alnum => !value.is_empty() && value.chars().all(char::is_alphanumeric),
+            Constraint::Bool => value == "true" || value == "false",
+            Constraint::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+impl PathPattern {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let segments = path
+            .split('/')
+            .map(Segment::parse)
+            .collect::<Result<Vec<Segment>, String>>()?;
+        let last = segments.len() - 1;
+        if segments[..last]
+            .iter()
+            .any(|s| matches!(s, Segment::CatchAll(_)))
+        {
+            return Err(format!("'**' must be the last segment of '{}'", path));
+        }
+        Ok(PathPattern { segments })
+    }
+
+    // Sort key for the path, lower is more specific
+    pub fn specificity(&self) -> Vec<u8> {
+        self.segments.iter().map(Segment::rank).collect()
+    }
+}
+
+// Endpoint paths of one method merged by segment, so a request is matched
+// against all of them in a single walk. Leaves hold the endpoint index.
+#[derive(Debug, Default)]
+pub struct RouteTrie {
+    root: TrieNode,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    literals: HashMap<String, TrieNode>,
+    // params and `*`, keyed by their source so equal segments share a node
+    dynamic: Vec<(String, Segment, TrieNode)>,
+    endpoints: Vec<usize>,
+    catch_all: Vec<(String, usize)>,
+}
+
+impl RouteTrie {
+    pub fn insert(&mut self, pattern: &PathPattern, id: usize) {
+        let mut node = &mut self.root;
+        for segment in &pattern.segments {
+            node = match segment {
+                Segment::Literal(literal) => node.literals.entry(literal.clone()).or_default(),
+                Segment::CatchAll(name) => {
+                    node.catch_all.push((name.clone(), id));
+                    return;
+                }
+                dynamic => {
+                    let key = dynamic.key();
+                    let i = match node.dynamic.iter().position(|(k, _, _)| *k == key) {
+                        Some(i) => i,
+                        None => {
+                            node.dynamic
+                                .push((key, dynamic.clone(), TrieNode::default()));
+                            node.dynamic.len() - 1
+                        }
+                    };
+                    &mut node.dynamic[i].2
+                }
+            };
+        }
+        node.endpoints.push(id);
+    }
+
+    // Every endpoint whose path matches, with its params, sorted by index
+    pub fn lookup(&self, path: &str) -> Vec<(usize, HashMap<String, String>)> {
+        let decoded: Vec<String> = path.split('/').map(|p| percent_decode(p, false)).collect();
+        let parts: Vec<&str> = decoded.iter().map(String::as_str).collect();
+        let mut found = Vec::new();
+        self.root.collect(&parts, &mut Vec::new(), &mut found);
+        found.sort_by_key(|(id, _)| *id);
+        found
+    }
+}
+
+impl TrieNode {
+    fn collect<'a>(
+        &'a self,
+        parts: &[&'a str],
+        captured: &mut Vec<(&'a str, &'a str)>,
+        found: &mut Vec<(usize, HashMap<String, String>)>,
+    ) {
+        let params = |captured: &[(&str, &str)]| -> HashMap<String, String> {
+            captured
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        for (name, id) in &self.catch_all {
+            let mut params = params(captured);
+            params.insert(name.clone(), parts.join("/"));
+            found.push((*id, params));
+        }
+        let Some((part, rest)) = parts.split_first() else {
+            for id in &self.endpoints {
+                found.push((*id, params(captured)));
+            }
+            return;
+        };
+        if let Some(child) = self.literals.get(*part) {
+            child.collect(rest, captured, found);
+        }
+        for (_, segment, child) in &self.dynamic {
+            match segment {
+                Segment::Param { name, constraint } => {
+                    if constraint.as_ref().is_some_and(|c| !c.is_match(part)) {
+                        continue;
+                    }
+                    captured.push((name, part));
+                    child.collect(rest, captured, found);
+                    captured.pop();
+                }
+                _ => child.collect(rest, captured, found),
+            }
+        }
+    }
+}
+
+impl Segment {
+    fn key(&self) -> String {
+        match self {
+            Segment::Literal(literal) => literal.clone(),
+            Segment::Param { name, constraint } => match constraint {
+                Some(constraint) => format!("{{{}:{}}}", name, constraint.key()),
+                None => format!("{{{}}}", name),
+            },
+            Segment::Wildcard => "*".to_string(),
+            Segment::CatchAll(name) => format!("{{{}:**}}", name),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 0,
+            Segment::Param {
+                constraint: Some(_),
+                ..
+            } => 1,
+            Segment::Param {
+                constraint: None, ..
+            }
+            | Segment::Wildcard => 2,
+            Segment::CatchAll(_) => 3,
+        }
+    }
+
+    fn parse(segment: &str) -> Result<Self, String> {
+        match segment {
+            "*" => return Ok(Segment::Wildcard),
+            "**" => return Ok(Segment::CatchAll("rest".to_string())),
+            _ => {}
+        }
+        let inner = segment
+            .strip_prefix("{{")
+            .and_then(|s| s.strip_suffix("}}"))
+            .or_else(|| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')));
+        let Some(inner) = inner else {
+            // compared against decoded request segments
+            return Ok(Segment::Literal(percent_decode(segment, false)));
+        };
+        let (name, constraint) = match inner.split_once(':') {
+            Some((name, constraint)) => (name.trim(), Some(constraint.trim())),
+            None => (inner.trim(), None),
+        };
+        if RESERVED_NAMES.contains(&name) {
+            return Err(format!("Path parameter '{}' is reserved", name));
+        }
+        match constraint {
+            Some("**") => Ok(Segment::CatchAll(name.to_string())),
+            constraint => Ok(Segment::Param {
+                name: name.to_string(),
+                constraint: constraint.map(Constraint::parse).transpose()?,
+            }),
+        }
+    }
+}
+
+// Host an endpoint or db is scoped to, matched against the Host header
+//   api.example.com       exact name, any port
+//   localhost:8081        name and port
+//   *.example.com         `*` stands for one label
+#[derive(Clone, Debug)]
+pub struct HostPattern {
+    labels: Vec<String>,
+    port: Option<String>,
+}
+
+impl HostPattern {
+    pub fn new(pattern: &str) -> Self {
+        let (name, port) = split_port(pattern.trim());
+        HostPattern {
+            labels: name.split('.').map(|l| l.to_lowercase()).collect(),
+            port: port.map(str::to_string),
+        }
+    }
+
+    pub fn is_match(&self, host: Option<&str>) -> bool {
+        let Some(host) = host else {
+            return false;
+        };
+        let (name, port) = split_port(host.trim());
+        if self.port.is_some() && self.port.as_deref() != port {
+            return false;
+        }
+        let labels: Vec<&str> = name.split('.').collect();
+        labels.len() == self.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(labels)
+                .all(|(p, l)| p == "*" || p.eq_ignore_ascii_case(l))
+    }
+}
+
+// "example.com:8080" -> ("example.com", Some("8080")), keeping IPv6 brackets
+fn split_port(host: &str) -> (&str, Option<&str>) {
+    let name_end = if host.starts_with('[') {
+        host.find(']').map_or(host.len(), |i| i + 1)
+    } else {
+        0
+    };
+    match host[name_end..].rfind(':') {
+        Some(i) => (&host[..name_end + i], Some(&host[name_end + i + 1..])),
+        None => (host, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+        let mut trie = RouteTrie::default();
+        trie.insert(&PathPattern::parse(pattern).unwrap(), 0);
+        trie.lookup(path).pop().map(|(_, params)| params)
+    }
+
+    #[test]
+    fn test_literal_and_untyped_params() {
+        assert!(params("/health", "/health").is_some());
+        assert!(params("/health", "/health/").is_none());
+        let p = params("/users/{{name}}/{{id}}", "/users/jane/7").unwrap();
+        assert_eq!(p["name"], "jane");
+        assert_eq!(p["id"], "7");
+        assert!(params("/users/{{name}}", "/users/jane/7").is_none());
+    }
+
+    #[test]
+    fn test_typed_params() {
+        assert!(params("/users/{{id:int}}", "/users/42").is_some());
+        assert!(params("/users/{{id:int}}", "/users/me").is_none());
+        assert!(params("/p/{{x:number}}", "/p/1.5").is_some());
+        assert!(params("/p/{{x:bool}}", "/p/yes").is_none());
+        let uuid = "3bed620f-6020-444b-b825-d06240bfa632";
+        assert!(params("/u/{{id:uuid}}", &format!("/u/{}", uuid)).is_some());
+        assert!(params("/u/{{id:uuid}}", "/u/3bed620f").is_none());
+    }
+
+    #[test]
+    fn test_regex_params() {
+        assert_eq!(
+            params("/posts/{{slug:[a-z-]+}}", "/posts/hello-world").unwrap()["slug"],
+            "hello-world"
+        );
+        // the regex has to match the whole segment
+        assert!(params("/posts/{{slug:[a-z-]+}}", "/posts/Hello").is_none());
+        assert!(params("/c/{{code:[0-9]{3}}}", "/c/123").is_some());
+        assert!(params("/c/{{code:[0-9]{3}}}", "/c/1234").is_none());
+        assert!(PathPattern::parse("/c/{{code:[0-9}}").is_err());
+    }
+
+    #[test]
+    fn test_single_wildcard() {
+        assert!(params("/static/*/logo.png", "/static/v2/logo.png").is_some());
+        assert!(params("/static/*/logo.png", "/static/a/b/logo.png").is_none());
+        assert!(params("/static/*", "/static/app.js").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_catch_all() {
+        let p = params("/files/**", "/files/a/b/c.txt").unwrap();
+        assert_eq!(p["rest"], "a/b/c.txt");
+        let p = params("/files/{{file:**}}", "/files/docs/readme.md").unwrap();
+        assert_eq!(p["file"], "docs/readme.md");
+        assert_eq!(params("/files/**", "/files").unwrap()["rest"], "");
+        assert!(params("/files/**", "/other/a").is_none());
+        assert_eq!(
+            params("/**", "/anything/at/all").unwrap()["rest"],
+            "anything/at/all"
+        );
+        assert!(PathPattern::parse("/files/**/edit").is_err());
+    }
+
+    #[test]
+    fn test_reserved_param_names() {
+        for path in [
+            "/files/{{path:**}}",
+            "/a/{{method}}",
+            "/u/{{uuid:uuid}}",
+            "/{{ now }}",
+        ] {
+            assert!(PathPattern::parse(path).is_err(), "{}", path);
+        }
+        assert!(PathPattern::parse("/files/{{paths}}").is_ok());
+    }
+
+    #[test]
+    fn test_decoded_segments() {
+        assert_eq!(
+            params("/users/{{name}}", "/users/Jos%C3%A9").unwrap()["name"],
+            "José"
+        );
+        assert_eq!(
+            params("/files/{{name}}", "/files/a%2Fb").unwrap()["name"],
+            "a/b"
+        );
+        assert_eq!(params("/q/{{term}}", "/q/a+b").unwrap()["term"], "a+b");
+        assert!(params("/caf%C3%A9", "/caf%C3%A9").is_some());
+        assert!(params("/caf%C3%A9", "/café").is_some());
+        assert!(params("/a%20b", "/a%20b").is_some());
+        assert!(params("/café", "/caf%C3%A9").is_some());
+    }
+
+    #[test]
+    fn test_specificity() {
+        let rank = |p: &str| PathPattern::parse(p).unwrap().specificity();
+        assert!(rank("/users/me") < rank("/users/{{id:int}}"));
+        assert!(rank("/users/{{id:int}}") < rank("/users/{{name}}"));
+        assert!(rank("/users/{{name}}") < rank("/users/**"));
+        assert!(rank("/users/*") < rank("/users/**"));
+        assert!(rank("/files/x/**") < rank("/files/**"));
+    }
+
+    #[test]
+    fn test_trie_shares_segments() {
+        let mut trie = RouteTrie::default();
+        for (id, path) in ["/users/{{id}}", "/users/{{id}}/posts", "/users/me", "/**"]
+            .iter()
+            .enumerate()
+        {
+            trie.insert(&PathPattern::parse(path).unwrap(), id);
+        }
+        assert_eq!(trie.root.literals[""].literals["users"].dynamic.len(), 1);
+        let ids = |path: &str| -> Vec<usize> { trie.lookup(path).iter().map(|m| m.0).collect() };
+        assert_eq!(ids("/users/me"), vec![0, 2, 3]);
+        assert_eq!(ids("/users/7/posts"), vec![1, 3]);
+        assert_eq!(ids("/nothing"), vec![3]);
+        assert_eq!(trie.lookup("/users/7/posts")[0].1["id"], "7");
+    }
+
+    #[test]
+    fn test_host_pattern() {
+        let host = HostPattern::new("billing.local");
+        assert!(host.is_match(Some("billing.local")));
+        assert!(host.is_match(Some("Billing.Local:8080")));
+        assert!(!host.is_match(Some("auth.local")));
+        assert!(!host.is_match(None));
+        let host = HostPattern::new("*.example.com");
+        assert!(host.is_match(Some("api.example.com")));
+        assert!(!host.is_match(Some("example.com")));
+        let host = HostPattern::new("localhost:8081");
+        assert!(host.is_match(Some("localhost:8081")));
+        assert!(!host.is_match(Some("localhost:8080")));
+        assert!(HostPattern::new("[::1]").is_match(Some("[::1]:8080")));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::db_handle::HttpErr;
+use hteapot::HttpStatus;
+use std::collections::HashMap;
+
+// Named state machines shared by endpoints
+//
+// [[endpoints.POST]]
+// path = "/cart/items"
+// scenario = "cart"
+// required_state = "Started"      only matches while cart is in this state
+// new_state = "Has items"         state of cart once it has been served
+//
+// Every scenario starts in "Started". The states can be read and reset with
+//   GET  /__cafetera/scenarios              {"cart": "Has items"}
+//   POST /__cafetera/scenarios/reset        every scenario back to Started
+//   POST /__cafetera/scenarios/cart/reset   only cart
+
+pub const STARTED: &str = "Started";
+pub const CONTROL_PATH: &str = "/__cafetera/scenarios";
+
+#[derive(Debug, Default)]
+pub struct Scenarios {
+    states: HashMap<String, String>,
+}
+
+impl Scenarios {
+    pub fn new<I: IntoIterator<Item = String>>(names: I) -> Self {
+        Scenarios {
+            states: names
+                .into_iter()
+                .map(|name| (name, STARTED.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn state(&self, name: &str) -> &str {
+        self.states.get(name).map_or(STARTED, |s| s.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, state: &str) {
+        self.states.insert(name.to_string(), state.to_string());
+    }
+
+    pub fn is_match(path: &str) -> bool {
+        path == CONTROL_PATH || path.starts_with(&format!("{}/", CONTROL_PATH))
+    }
+
+    pub fn process(&mut self, method: &str, path: &str) -> Result<String, HttpErr> {
+        let rest = path[CONTROL_PATH.len()..].trim_matches('/');
+        let parts: Vec<&str> = rest.split('/').filter(|p| !p.is_empty()).collect();
+        match (method, parts.as_slice()) {
+            ("GET", []) => serde_json::to_string(&self.states).map_err(|_| HttpErr {
+                status: HttpStatus::InternalServerError,
+                text: "Error parsing result",
+            }),
+            ("POST", ["reset"]) => {
+                for state in self.states.values_mut() {
+                    *state = STARTED.to_string();
+                }
+                Ok("{}".to_string())
+            }
+            ("POST", [name, "reset"]) => match self.states.get_mut(*name) {
+                Some(state) => {
+                    *state = STARTED.to_string();
+                    Ok("{}".to_string())
+                }
+                None => Err(HttpErr {
+                    status: HttpStatus::NotFound,
+                    text: "Unknown scenario",
+                }),
+            },
+            (_, []) | (_, ["reset"]) | (_, [_, "reset"]) => Err(HttpErr {
+                status: HttpStatus::MethodNotAllowed,
+                text: "Method Not Allowed",
+            }),
+            _ => Err(HttpErr {
+                status: HttpStatus::NotFound,
+                text: "Not Found",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenarios() -> Scenarios {
+        Scenarios::new(vec!["cart".to_string(), "login".to_string()])
+    }
+
+    #[test]
+    fn test_states() {
+        let mut s = scenarios();
+        assert_eq!(s.state("cart"), STARTED);
+        s.set("cart", "Has items");
+        assert_eq!(s.state("cart"), "Has items");
+        assert_eq!(s.state("unknown"), STARTED);
+    }
+
+    #[test]
+    fn test_control_paths() {
+        assert!(Scenarios::is_match("/__cafetera/scenarios"));
+        assert!(Scenarios::is_match("/__cafetera/scenarios/cart/reset"));
+        assert!(!Scenarios::is_match("/__cafetera/scenariosx"));
+        assert!(!Scenarios::is_match("/cart"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut s = scenarios();
+        s.set("cart", "Checked out");
+        s.set("login", "Logged in");
+        assert!(s
+            .process("POST", "/__cafetera/scenarios/cart/reset")
+            .is_ok());
+        assert_eq!(s.state("cart"), STARTED);
+        assert_eq!(s.state("login"), "Logged in");
+        assert!(s.process("POST", "/__cafetera/scenarios/reset").is_ok());
+        assert_eq!(s.state("login"), STARTED);
+        let unknown = s.process("POST", "/__cafetera/scenarios/nope/reset");
+        assert!(unknown.is_err_and(|e| e.status == HttpStatus::NotFound));
+        let wrong_method = s.process("DELETE", "/__cafetera/scenarios/reset");
+        assert!(wrong_method.is_err_and(|e| e.status == HttpStatus::MethodNotAllowed));
+    }
+
+    #[test]
+    fn test_list_states() {
+        let mut s = Scenarios::new(vec!["cart".to_string()]);
+        s.set("cart", "Has items");
+        let list = s.process("GET", "/__cafetera/scenarios");
+        assert_eq!(list.ok().unwrap(), r#"{"cart":"Has items"}"#);
+    }
+}
@@ -0,0 +1,1196 @@
+use hteapot::HttpRequest;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::utils::{decode_args, now_millis, parse_body, query_string, SimpleRNG};
+use crate::{datetime, fake, filters};
+
+// Template engine for endpoint bodies
+// Supports handlebars-like syntax:
+//   {{arg.name}}                       variable lookup
+//   {{rand}} {{uuid}} {{fake.email}}   helper calls
+//   {{fake.lorem 12}}                  helper with arguments
+//   {{#if arg.debug == "true"}} .. {{else}} .. {{/if}}
+//   {{#unless header.authorization}} .. {{/unless}}
+//   {{#each body.items}} {{@index}}: {{this.name}} {{else}} empty {{/each}}
+//   {{body.user.name}} {{body./items/0/id}}  fields of a JSON request body
+//   {{arg.name | upper}} {{header.x | default "anon"}}  filters, see filters.rs
+//   {{! comment }}
+// Inside {{#each}} lookups are resolved against the current item first and
+// then against the outer scopes, `../name` skips one scope up.
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Expr(Expr, Escape),
+    If {
+        cond: Cond,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        expr: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+// How a value is written into the output, picked at parse time from where the
+// tag sits in a JSON body
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    // Plain text bodies
+    None,
+    // {{{raw}}} and {{& raw}} tags are never escaped
+    Raw,
+    // Inside a JSON string literal: "name": "{{arg.name}}"
+    JsonString,
+    // Outside of a string: "id": {{arg.id}}
+    JsonValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+struct Cond {
+    left: Expr,
+    cmp: Option<(CmpOp, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Literal(Value),
+    Path(Path),
+}
+
+#[derive(Debug, Clone)]
+struct Path {
+    name: String,
+    parents: usize,
+    segments: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    head: Operand,
+    args: Vec<Operand>,
+    hash: Vec<(String, Operand)>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    name: String,
+    args: Vec<Operand>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Key(String),
+    Op(CmpOp),
+    Pipe,
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Tag(&'a str),
+    Raw(&'a str),
+}
+
+// Names a template reads as request data, a helper or a keyword, so a path
+// param called one of them could never be reached
+pub const RESERVED_NAMES: &[&str] = &[
+    "path",
+    "method",
+    "body",
+    "query",
+    "arg",
+    "header",
+    "this",
+    "else",
+    "true",
+    "false",
+    "null",
+    "rand",
+    "randFloat",
+    "pick",
+    "now",
+    "uuid",
+    "fake",
+];
+
+// Request data the templates are rendered against
+pub struct Context {
+    root: Value,
+    body: Value,
+    header_default: Value,
+}
+
+pub struct RenderState<'a> {
+    pub ctx: &'a Context,
+    pub rng: &'a mut SimpleRNG,
+}
+
+struct Scope<'a> {
+    this: &'a Value,
+    index: Option<usize>,
+    key: Option<String>,
+    len: usize,
+    parent: Option<&'a Scope<'a>>,
+}
+
+impl Context {
+    pub fn new(root: Value) -> Self {
+        Context {
+            root,
+            body: Value::Null,
+            header_default: Value::String(String::new()),
+        }
+    }
+
+    pub fn from_request(
+        req: &HttpRequest,
+        body: &str,
+        path_args: HashMap<String, String>,
+        header_default: &str,
+    ) -> Self {
+        let mut root: Map<String, Value> = path_args
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+        let args: Map<String, Value> = decode_args(&req.args)
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+        let headers: Map<String, Value> = req
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), Value::String(v.clone())))
+            .collect();
+        root.insert("path".to_string(), Value::String(req.path.clone()));
+        root.insert(
+            "method".to_string(),
+            Value::String(req.method.to_str().to_string()),
+        );
+        root.insert("body".to_string(), Value::String(body.to_string()));
+        root.insert("query".to_string(), Value::String(query_string(&req.args)));
+        root.insert("arg".to_string(), Value::Object(args));
+        root.insert("header".to_string(), Value::Object(headers));
+        let mut ctx = Context::new(Value::Object(root));
+        ctx.body = parse_body(body).unwrap_or(Value::Null);
+        ctx.header_default = Value::String(header_default.to_string());
+        ctx
+    }
+}
+
+impl Template {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let parts = split_parts(src);
+        let mut iter = parts.into_iter();
+        let (mut nodes, end) = parse_nodes(&mut iter)?;
+        if let Some(end) = end {
+            return Err(format!("Unexpected {{{{{}}}}}", end));
+        }
+        if is_json_template(&nodes) {
+            annotate_json(&mut nodes, false);
+        }
+        Ok(Template { nodes })
+    }
+
+    // The text of templates without any tag, which don't need rendering
+    pub fn static_text(&self) -> Option<String> {
+        let mut text = String::new();
+        for node in &self.nodes {
+            match node {
+                Node::Text(part) => text.push_str(part),
+                _ => return None,
+            }
+        }
+        Some(text)
+    }
+
+    pub fn render(&self, ctx: &Context, rng: &mut SimpleRNG) -> String {
+        let mut state = RenderState { ctx, rng };
+        let scope = Scope {
+            this: &ctx.root,
+            index: None,
+            key: None,
+            len: 0,
+            parent: None,
+        };
+        let mut out = String::new();
+        render_nodes(&self.nodes, &scope, &mut state, &mut out);
+        out
+    }
+}
+
+// `\{{` writes a literal `{{`, and a `{{` that is never closed is kept as
+// text, so stray braces in HTML or scripts don't make the body invalid
+fn split_parts(src: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = src;
+    while let Some(start) = rest.find("{{") {
+        if let Some(text) = rest[..start].strip_suffix('\\') {
+            parts.push(Part::Text(text));
+            parts.push(Part::Text("{{"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+        if start > 0 {
+            parts.push(Part::Text(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if let Some(inner) = rest.strip_prefix("{{{") {
+            if let Some(end) = inner.find("}}}") {
+                parts.push(Part::Raw(inner[..end].trim()));
+                rest = &inner[end + 3..];
+                continue;
+            }
+            // `{{{x}}` is a `{` followed by a regular tag
+            parts.push(Part::Text("{"));
+            rest = &rest[1..];
+            continue;
+        }
+        let inner = &rest[2..];
+        let Some(end) = inner.find("}}") else {
+            break;
+        };
+        // a tag can't hold `{{`, so these braces are stray text
+        if inner[..end].contains("{{") {
+            parts.push(Part::Text("{{"));
+            rest = inner;
+            continue;
+        }
+        parts.push(Part::Tag(inner[..end].trim()));
+        rest = &inner[end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    parts
+}
+
+// Parses nodes until a closing or `else` tag, which is returned to the caller
+fn parse_nodes<'a, I>(parts: &mut I) -> Result<(Vec<Node>, Option<String>), String>
+where
+    I: Iterator<Item = Part<'a>>,
+{
+    let mut nodes = Vec::new();
+    while let Some(part) = parts.next() {
+        let tag = match part {
+            Part::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Part::Raw(tag) => {
+                nodes.push(Node::Expr(parse_expr(tokenize(tag)?)?, Escape::Raw));
+                continue;
+            }
+            Part::Tag(tag) => tag,
+        };
+        if let Some(raw) = tag.strip_prefix('&') {
+            nodes.push(Node::Expr(parse_expr(tokenize(raw)?)?, Escape::Raw));
+            continue;
+        }
+        if tag.starts_with('!') {
+            continue;
+        }
+        if tag == "else" || tag.starts_with('/') {
+            return Ok((nodes, Some(tag.to_string())));
+        }
+        if let Some(block) = tag.strip_prefix('#') {
+            let (name, rest) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+            let (body, otherwise) = parse_block(parts, name)?;
+            let node = match name {
+                "if" | "unless" => Node::If {
+                    cond: parse_cond(rest)?,
+                    negate: name == "unless",
+                    then: body,
+                    otherwise,
+                },
+                "each" => Node::Each {
+                    expr: parse_expr(tokenize(rest)?)?,
+                    body,
+                    otherwise,
+                },
+                _ => return Err(format!("Unknown block helper '{}'", name)),
+            };
+            nodes.push(node);
+            continue;
+        }
+        // Escaping is decided once the whole template is known
+        nodes.push(Node::Expr(parse_expr(tokenize(tag)?)?, Escape::None));
+    }
+    Ok((nodes, None))
+}
+
+// Bodies whose literal text starts like a JSON document get their values
+// escaped, so quotes or newlines in a request can't break the response.
+// Leading tags are skipped and blocks are looked into, so a body wrapped in
+// {{#if}} is still JSON. An escaped \{{ is text but never starts a document.
+fn is_json_template(nodes: &[Node]) -> bool {
+    first_text(nodes).is_some_and(|text| {
+        text.starts_with('[') || (text.starts_with('{') && !text.starts_with("{{"))
+    })
+}
+
+fn first_text(nodes: &[Node]) -> Option<&str> {
+    nodes.iter().find_map(|node| match node {
+        Node::Text(text) => Some(text.trim_start()).filter(|t| !t.is_empty()),
+        Node::Expr(..) => None,
+        Node::If {
+            then, otherwise, ..
+        } => first_text(then).or_else(|| first_text(otherwise)),
+        Node::Each {
+            body, otherwise, ..
+        } => first_text(body).or_else(|| first_text(otherwise)),
+    })
+}
+
+// Marks each tag with its position relative to JSON string literals and
+// returns whether the text after `nodes` is still inside a string
+fn annotate_json(nodes: &mut [Node], mut in_string: bool) -> bool {
+    for node in nodes.iter_mut() {
+        match node {
+            Node::Text(text) => {
+                let mut escaped = false;
+                for c in text.chars() {
+                    if escaped {
+                        escaped = false;
+                    } else if in_string && c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        in_string = !in_string;
+                    }
+                }
+            }
+            Node::Expr(_, Escape::Raw) => {}
+            Node::Expr(expr, escape) => {
+                let escaped_by_filter = expr
+                    .filters
+                    .last()
+                    .is_some_and(|f| filters::escapes_json(&f.name));
+                *escape = match in_string {
+                    // `json_escape` already made the value safe in a string
+                    true if escaped_by_filter => Escape::Raw,
+                    true => Escape::JsonString,
+                    false => Escape::JsonValue,
+                };
+            }
+            Node::If {
+                then, otherwise, ..
+            }
+            | Node::Each {
+                body: then,
+                otherwise,
+                ..
+            } => {
+                annotate_json(otherwise, in_string);
+                in_string = annotate_json(then, in_string);
+            }
+        }
+    }
+    in_string
+}
+
+fn parse_block<'a, I>(parts: &mut I, name: &str) -> Result<(Vec<Node>, Vec<Node>), String>
+where
+    I: Iterator<Item = Part<'a>>,
+{
+    let close = format!("/{}", name);
+    let (body, end) = parse_nodes(parts)?;
+    match end.as_deref() {
+        Some("else") => {
+            let (otherwise, end) = parse_nodes(parts)?;
+            if end.as_deref() != Some(close.as_str()) {
+                return Err(format!("Missing {{{{{}}}}}", close));
+            }
+            Ok((body, otherwise))
+        }
+        Some(end) if end == close => Ok((body, Vec::new())),
+        _ => Err(format!("Missing {{{{{}}}}}", close)),
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '|' {
+            chars.next();
+            tokens.push(Token::Pipe);
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => text.extend(chars.next()),
+                    Some(ch) if ch == c => break,
+                    Some(ch) => text.push(ch),
+                    None => return Err(format!("Unclosed string in '{}'", src)),
+                }
+            }
+            tokens.push(Token::Str(text));
+            continue;
+        }
+        let mut word = String::new();
+        let mut is_key = false;
+        while let Some(&ch) = chars.peek() {
+            if ch.is_whitespace() || ch == '"' || ch == '\'' || ch == '|' {
+                break;
+            }
+            chars.next();
+            // `name=value` introduces a hash argument, `==`/`!=`/`<=`/`>=` are operators
+            if ch == '=' && !word.is_empty() && !is_operator_prefix(&word) {
+                is_key = true;
+                break;
+            }
+            word.push(ch);
+        }
+        let token = match word.as_str() {
+            "==" => Token::Op(CmpOp::Eq),
+            "!=" => Token::Op(CmpOp::Ne),
+            "<" => Token::Op(CmpOp::Lt),
+            "<=" => Token::Op(CmpOp::Le),
+            ">" => Token::Op(CmpOp::Gt),
+            ">=" => Token::Op(CmpOp::Ge),
+            _ if is_key => Token::Key(word),
+            _ => Token::Word(word),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_operator_prefix(word: &str) -> bool {
+    matches!(word, "=" | "!" | "<" | ">")
+}
+
+fn parse_operand(token: &Token) -> Result<Operand, String> {
+    match token {
+        Token::Str(text) => Ok(Operand::Literal(Value::String(text.clone()))),
+        Token::Word(word) => Ok(parse_word(word)),
+        Token::Key(key) => Err(format!("Unexpected '{}'", key)),
+        Token::Op(_) => Err("Unexpected operator".to_string()),
+        Token::Pipe => Err("Unexpected '|'".to_string()),
+    }
+}
+
+fn parse_word(word: &str) -> Operand {
+    match word {
+        "true" => return Operand::Literal(Value::Bool(true)),
+        "false" => return Operand::Literal(Value::Bool(false)),
+        "null" => return Operand::Literal(Value::Null),
+        _ => {}
+    }
+    if let Ok(n) = word.parse::<i64>() {
+        return Operand::Literal(Value::from(n));
+    }
+    if let Ok(n) = word.parse::<f64>() {
+        return Operand::Literal(Value::from(n));
+    }
+    let mut rest = word;
+    let mut parents = 0;
+    while let Some(r) = rest.strip_prefix("../") {
+        parents += 1;
+        rest = r;
+    }
+    // `body./items/0/id` addresses the rest of the path as a JSON pointer
+    let (dotted, pointer) = match rest.split_once("./") {
+        Some((dotted, pointer)) => (dotted, Some(format!("/{}", pointer))),
+        None => (rest, None),
+    };
+    let mut segments: Vec<String> = dotted.split('.').map(|s| s.to_string()).collect();
+    segments.extend(pointer);
+    Operand::Path(Path {
+        name: rest.to_string(),
+        parents,
+        segments,
+    })
+}
+
+fn parse_expr(tokens: Vec<Token>) -> Result<Expr, String> {
+    let mut chunks = tokens.split(|t| *t == Token::Pipe);
+    let mut tokens = chunks.next().unwrap_or_default().iter();
+    let head = parse_operand(tokens.next().ok_or("Empty expression")?)?;
+    let mut args = Vec::new();
+    let mut hash = Vec::new();
+    while let Some(token) = tokens.next() {
+        if let Token::Key(key) = token {
+            let value = tokens
+                .next()
+                .ok_or(format!("Missing value for '{}'", key))?;
+            hash.push((key.clone(), parse_operand(value)?));
+        } else {
+            args.push(parse_operand(token)?);
+        }
+    }
+    let mut filters = Vec::new();
+    for chunk in chunks {
+        let name = match chunk.first() {
+            Some(Token::Word(name)) if filters::exists(name) => name.clone(),
+            Some(Token::Word(name)) => return Err(format!("Unknown filter '{}'", name)),
+            _ => return Err("Missing filter name after '|'".to_string()),
+        };
+        let args = chunk[1..]
+            .iter()
+            .map(parse_operand)
+            .collect::<Result<Vec<Operand>, String>>()?;
+        filters.push(Filter { name, args });
+    }
+    Ok(Expr {
+        head,
+        args,
+        hash,
+        filters,
+    })
+}
+
+fn parse_cond(src: &str) -> Result<Cond, String> {
+    let mut tokens = tokenize(src)?;
+    let op_pos = tokens.iter().position(|t| matches!(t, Token::Op(_)));
+    match op_pos {
+        Some(pos) => {
+            let right = tokens.split_off(pos + 1);
+            let op = match tokens.pop() {
+                Some(Token::Op(op)) => op,
+                _ => return Err(format!("Invalid condition '{}'", src)),
+            };
+            Ok(Cond {
+                left: parse_expr(tokens)?,
+                cmp: Some((op, parse_expr(right)?)),
+            })
+        }
+        None => Ok(Cond {
+            left: parse_expr(tokens)?,
+            cmp: None,
+        }),
+    }
+}
+
+fn render_nodes(nodes: &[Node], scope: &Scope, state: &mut RenderState, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Expr(expr, escape) => {
+                let value = eval_expr(expr, scope, state);
+                let text = stringify(&value);
+                match escape {
+                    Escape::None | Escape::Raw => out.push_str(&text),
+                    Escape::JsonString => out.push_str(&json_escape(&text)),
+                    Escape::JsonValue => out.push_str(&json_value(&value, text)),
+                }
+            }
+            Node::If {
+                cond,
+                negate,
+                then,
+                otherwise,
+            } => {
+                if eval_cond(cond, scope, state) != *negate {
+                    render_nodes(then, scope, state, out);
+                } else {
+                    render_nodes(otherwise, scope, state, out);
+                }
+            }
+            Node::Each {
+                expr,
+                body,
+                otherwise,
+            } => {
+                let items: Vec<(Option<String>, Value)> = match eval_expr(expr, scope, state) {
+                    Value::Array(list) => list.into_iter().map(|v| (None, v)).collect(),
+                    Value::Object(map) => map.into_iter().map(|(k, v)| (Some(k), v)).collect(),
+                    _ => Vec::new(),
+                };
+                if items.is_empty() {
+                    render_nodes(otherwise, scope, state, out);
+                    continue;
+                }
+                let len = items.len();
+                for (index, (key, this)) in items.into_iter().enumerate() {
+                    let child = Scope {
+                        this: &this,
+                        index: Some(index),
+                        key,
+                        len,
+                        parent: Some(scope),
+                    };
+                    render_nodes(body, &child, state, out);
+                }
+            }
+        }
+    }
+}
+
+fn eval_cond(cond: &Cond, scope: &Scope, state: &mut RenderState) -> bool {
+    let left = eval_expr(&cond.left, scope, state);
+    match &cond.cmp {
+        None => is_truthy(&left),
+        Some((op, right)) => {
+            let right = eval_expr(right, scope, state);
+            compare(&left, *op, &right)
+        }
+    }
+}
+
+fn compare(left: &Value, op: CmpOp, right: &Value) -> bool {
+    let ordering = match (as_number(left), as_number(right)) {
+        (Some(l), Some(r)) => l.partial_cmp(&r),
+        _ => Some(stringify(left).cmp(&stringify(right))),
+    };
+    let Some(ordering) = ordering else {
+        return op == CmpOp::Ne;
+    };
+    match op {
+        CmpOp::Eq => ordering.is_eq(),
+        CmpOp::Ne => ordering.is_ne(),
+        CmpOp::Lt => ordering.is_lt(),
+        CmpOp::Le => ordering.is_le(),
+        CmpOp::Gt => ordering.is_gt(),
+        CmpOp::Ge => ordering.is_ge(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(list) => !list.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+// Escapes text to be placed between the quotes of a JSON string
+pub fn json_escape(text: &str) -> String {
+    let quoted = Value::String(text.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// Text that is already valid JSON is kept as is, anything else is quoted
+fn json_value(value: &Value, text: String) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::String(_) if serde_json::from_str::<Value>(&text).is_err() => {
+            Value::String(text).to_string()
+        }
+        Value::String(_) => text,
+        other => other.to_string(),
+    }
+}
+
+pub fn stringify(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn eval_operand(operand: &Operand, scope: &Scope, state: &RenderState) -> Value {
+    match operand {
+        Operand::Literal(value) => value.clone(),
+        Operand::Path(path) => lookup(path, scope, state.ctx),
+    }
+}
+
+fn eval_expr(expr: &Expr, scope: &Scope, state: &mut RenderState) -> Value {
+    let mut value = eval_head(expr, scope, state);
+    for filter in &expr.filters {
+        let args: Vec<Value> = filter
+            .args
+            .iter()
+            .map(|a| eval_operand(a, scope, state))
+            .collect();
+        value = filters::apply(&filter.name, value, &args);
+    }
+    value
+}
+
+fn eval_head(expr: &Expr, scope: &Scope, state: &mut RenderState) -> Value {
+    if let Operand::Path(path) = &expr.head {
+        if path.parents == 0 {
+            let args: Vec<Value> = expr
+                .args
+                .iter()
+                .map(|a| eval_operand(a, scope, state))
+                .collect();
+            let hash: HashMap<String, Value> = expr
+                .hash
+                .iter()
+                .map(|(k, v)| (k.clone(), eval_operand(v, scope, state)))
+                .collect();
+            if let Some(value) = call_helper(&path.name, &args, &hash, state) {
+                return value;
+            }
+        }
+    }
+    eval_operand(&expr.head, scope, state)
+}
+
+fn call_helper(
+    name: &str,
+    args: &[Value],
+    hash: &HashMap<String, Value>,
+    state: &mut RenderState,
+) -> Option<Value> {
+    let value = match name {
+        "rand" => rand(args, state.rng),
+        "randFloat" => rand_float(args, hash, state.rng),
+        "pick" => match args.len() {
+            0 => Value::Null,
+            len => args[state.rng.next_range(0, len as u64) as usize].clone(),
+        },
+        "now" => now_helper(hash),
+        "query.json" => state.ctx.root["arg"].clone(),
+        "uuid" => Value::String(fake::uuid(state.rng)),
+        _ => {
+            return name
+                .strip_prefix("fake.")
+                .and_then(|kind| fake::generate(kind, args, state.rng))
+        }
+    };
+    Some(value)
+}
+
+// {{now}} with optional offset, unit, format and tz, see datetime.rs
+fn now_helper(hash: &HashMap<String, Value>) -> Value {
+    let option = |key: &str| hash.get(key).map(stringify);
+    let mut millis = now_millis();
+    if let Some(offset) = option("offset") {
+        match datetime::parse_offset(&offset).map(|offset| millis.checked_add(offset)) {
+            Ok(Some(shifted)) => millis = shifted,
+            _ => return Value::Null,
+        }
+    }
+    let tz = match option("tz").map(|tz| datetime::parse_tz(&tz)) {
+        Some(Ok(tz)) => tz,
+        Some(Err(_)) => return Value::Null,
+        None => 0,
+    };
+    if let Some(format) = option("format") {
+        return Value::String(datetime::format(millis, &format, tz));
+    }
+    match option("unit").as_deref() {
+        Some("ms") => Value::from(millis),
+        _ => Value::from(millis.div_euclid(1_000)),
+    }
+}
+
+fn number_arg(args: &[Value], index: usize) -> Option<f64> {
+    args.get(index).and_then(as_number)
+}
+
+// {{rand}} is 0..=99, {{rand max}} is 0..=max and {{rand min max}} is min..=max
+fn rand(args: &[Value], rng: &mut SimpleRNG) -> Value {
+    let (min, max) = match (number_arg(args, 0), number_arg(args, 1)) {
+        (Some(min), Some(max)) => (min as i64, max as i64),
+        (Some(max), None) => (0, max as i64),
+        _ => (0, 99),
+    };
+    let (min, max) = (min.min(max) as i128, min.max(max) as i128);
+    // i64::MIN..=i64::MAX has one value more than u64 can count
+    let span = u64::try_from(max - min + 1).unwrap_or(u64::MAX);
+    Value::from((min + rng.next_range(0, span) as i128) as i64)
+}
+
+// {{randFloat}} is in [0, 1), {{randFloat min max decimals=2}} is in [min, max)
+fn rand_float(args: &[Value], hash: &HashMap<String, Value>, rng: &mut SimpleRNG) -> Value {
+    let min = number_arg(args, 0).unwrap_or(0.0);
+    let max = number_arg(args, 1).unwrap_or(1.0);
+    let mut value = min + rng.next_f64() * (max - min);
+    if let Some(decimals) = hash.get("decimals").and_then(as_number) {
+        let factor = 10f64.powi(decimals as i32);
+        value = (value * factor).floor() / factor;
+    }
+    Value::from(value)
+}
+
+fn lookup(path: &Path, scope: &Scope, ctx: &Context) -> Value {
+    let mut scope = scope;
+    for _ in 0..path.parents {
+        match scope.parent {
+            Some(parent) => scope = parent,
+            None => break,
+        }
+    }
+    let first = path.segments[0].as_str();
+    match first {
+        "this" => return walk(scope.this, &path.segments[1..]),
+        "@index" => return scope.index.map(Value::from).unwrap_or(Value::Null),
+        "@key" => return scope.key.clone().map(Value::String).unwrap_or(Value::Null),
+        "@first" => return Value::Bool(scope.index == Some(0)),
+        "@last" => return Value::Bool(scope.index.map(|i| i + 1) == Some(scope.len)),
+        _ => {}
+    }
+    let mut current = Some(scope);
+    while let Some(s) = current {
+        if s.parent.is_none() {
+            return lookup_root(&path.segments, s.this, ctx);
+        }
+        if s.this.get(first).is_some() {
+            return walk(s.this, &path.segments);
+        }
+        current = s.parent;
+    }
+    Value::Null
+}
+
+fn lookup_root(segments: &[String], root: &Value, ctx: &Context) -> Value {
+    if segments[0] == "header" && segments.len() > 1 {
+        let name = segments[1..].join(".").to_lowercase();
+        return root["header"]
+            .get(&name)
+            .cloned()
+            .unwrap_or_else(|| ctx.header_default.clone());
+    }
+    if segments[0] == "body" && segments.len() > 1 {
+        return walk(&ctx.body, &segments[1..]);
+    }
+    walk(root, segments)
+}
+
+fn walk(value: &Value, segments: &[String]) -> Value {
+    let mut current = value;
+    for segment in segments {
+        if segment.starts_with('/') {
+            match current.pointer(segment) {
+                Some(v) => current = v,
+                None => return Value::Null,
+            }
+            continue;
+        }
+        let next = match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(list) => segment.parse::<usize>().ok().and_then(|i| list.get(i)),
+            _ => None,
+        };
+        match next {
+            Some(v) => current = v,
+            None => return Value::Null,
+        }
+    }
+    current.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(src: &str, root: Value) -> String {
+        let ctx = Context::new(root);
+        Template::parse(src)
+            .unwrap()
+            .render(&ctx, &mut SimpleRNG::new())
+    }
+
+    #[test]
+    fn test_variables() {
+        let root = json!({"path": "/users", "arg": {"name": "Jane"}, "id": "7"});
+        assert_eq!(
+            render("{{path}} {{arg.name}} {{ id }}", root),
+            "/users Jane 7"
+        );
+    }
+
+    #[test]
+    fn test_query_from_request() {
+        let mut req = HttpRequest::new(hteapot::HttpMethod::GET, "/search");
+        req.args.insert("q".to_string(), "caf%C3%A9".to_string());
+        req.args.insert("page".to_string(), "2".to_string());
+        let ctx = Context::from_request(&req, "", HashMap::new(), "");
+        let render = |src: &str| {
+            Template::parse(src)
+                .unwrap()
+                .render(&ctx, &mut SimpleRNG::new())
+        };
+        // rebuilt from the parsed args, sorted by name
+        assert_eq!(render("{{query}}"), "page=2&q=caf%C3%A9");
+        assert_eq!(
+            render("{\"args\": {{query.json}}}"),
+            r#"{"args": {"page":"2","q":"café"}}"#
+        );
+    }
+
+    #[test]
+    fn test_missing_variable_is_empty() {
+        assert_eq!(render("<{{arg.missing}}>", json!({"arg": {}})), "<>");
+        assert_eq!(render("[{{arg.missing}}]", json!({"arg": {}})), "[null]");
+    }
+
+    #[test]
+    fn test_if_else() {
+        let src = "{{#if arg.debug == \"true\"}}debug{{else}}quiet{{/if}}";
+        assert_eq!(render(src, json!({"arg": {"debug": "true"}})), "debug");
+        assert_eq!(render(src, json!({"arg": {"debug": "no"}})), "quiet");
+        assert_eq!(render(src, json!({"arg": {}})), "quiet");
+    }
+
+    #[test]
+    fn test_numeric_comparison() {
+        let src = "{{#if arg.age >= 18}}adult{{else}}minor{{/if}}";
+        assert_eq!(render(src, json!({"arg": {"age": "21"}})), "adult");
+        assert_eq!(render(src, json!({"arg": {"age": "9"}})), "minor");
+    }
+
+    #[test]
+    fn test_unless() {
+        let src = "{{#unless header.authorization}}401{{/unless}}";
+        assert_eq!(render(src, json!({"header": {}})), "401");
+    }
+
+    #[test]
+    fn test_each_with_nested_scopes() {
+        let src =
+            "{{#each items}}{{@index}}:{{name}}@{{owner}}{{#unless @last}},{{/unless}}{{/each}}";
+        let root = json!({"owner": "root", "items": [{"name": "a"}, {"name": "b"}]});
+        assert_eq!(render(src, root), "0:a@root,1:b@root");
+    }
+
+    #[test]
+    fn test_each_else_and_parent_scope() {
+        let src = "{{#each items}}{{this}}-{{../name}}{{else}}none{{/each}}";
+        assert_eq!(render(src, json!({"name": "x", "items": [1, 2]})), "1-x2-x");
+        assert_eq!(render(src, json!({"items": []})), "none");
+    }
+
+    fn render_body(src: &str, body: &str) -> String {
+        let mut ctx = Context::new(json!({"body": body}));
+        ctx.body = parse_body(body).unwrap_or(Value::Null);
+        Template::parse(src)
+            .unwrap()
+            .render(&ctx, &mut SimpleRNG::new())
+    }
+
+    #[test]
+    fn test_body_fields() {
+        let body = r#"{"user": {"name": "Jane", "age": 30}, "items": [{"id": 7}]}"#;
+        let src = r#"{"name": "{{body.user.name}}", "age": {{body.user.age}}, "id": {{body./items/0/id}}}"#;
+        assert_eq!(
+            render_body(src, body),
+            r#"{"name": "Jane", "age": 30, "id": 7}"#
+        );
+    }
+
+    #[test]
+    fn test_missing_body_fields_render_null() {
+        let src = r#"{"name": {{body.user.name}}, "id": {{body./items/3/id}}}"#;
+        assert_eq!(
+            render_body(src, "not json"),
+            r#"{"name": null, "id": null}"#
+        );
+        assert_eq!(render_body("{{body}}", "not json"), "not json");
+        // inside a string or plain text a missing field is empty
+        let src = r#"{"name": "{{body.user.name}}"}"#;
+        assert_eq!(render_body(src, "{}"), r#"{"name": ""}"#);
+        assert_eq!(render_body("Hello {{body.user.name}}", "{}"), "Hello ");
+    }
+
+    #[test]
+    fn test_each_over_body() {
+        let src = "{{#each body.items}}{{id}};{{/each}}";
+        assert_eq!(
+            render_body(src, r#"{"items": [{"id": 1}, {"id": 2}]}"#),
+            "1;2;"
+        );
+    }
+
+    #[test]
+    fn test_fake_helpers() {
+        let out = render("{{uuid}}|{{fake.hex 6}}|{{fake.nope}}", json!({}));
+        let parts: Vec<&str> = out.split('|').collect();
+        assert_eq!(parts[0].len(), 36);
+        assert_eq!(parts[1].len(), 6);
+        assert_eq!(parts[2], "");
+    }
+
+    #[test]
+    fn test_random_helpers_with_seed() {
+        let ctx = Context::new(json!({}));
+        let template =
+            Template::parse("{{rand 5 7}} {{randFloat 1 2 decimals=2}} {{pick \"a\" \"b\"}}")
+                .unwrap();
+        let first = template.render(&ctx, &mut SimpleRNG::with_seed(3));
+        assert_eq!(first, template.render(&ctx, &mut SimpleRNG::with_seed(3)));
+        let parts: Vec<&str> = first.split(' ').collect();
+        assert!((5..=7).contains(&parts[0].parse::<i64>().unwrap()));
+        let float = parts[1].parse::<f64>().unwrap();
+        assert!((1.0..2.0).contains(&float));
+        assert!(parts[1].len() <= 4);
+        assert!(parts[2] == "a" || parts[2] == "b");
+    }
+
+    #[test]
+    fn test_rand_extreme_bounds() {
+        let root = json!({"arg": {"a": "-9e18", "b": "9e18", "max": "1e30"}});
+        let out = render("{{rand arg.a arg.b}}", root.clone());
+        assert!(out.parse::<i64>().is_ok());
+        let out = render("{{rand arg.max}}", root);
+        assert!(out.parse::<i64>().unwrap() >= 0);
+    }
+
+    #[test]
+    fn test_filters() {
+        let root = json!({"arg": {"name": " Jane "}, "header": {}});
+        let src = "{{arg.name | trim | upper}} {{header.x-user | default \"anon\"}} {{arg.name | trim | base64}}";
+        assert_eq!(render(src, root), "JANE anon SmFuZQ==");
+        let src = "{{#if arg.name | trim | lower == \"jane\"}}ok{{/if}}";
+        assert_eq!(render(src, json!({"arg": {"name": " JANE"}})), "ok");
+        assert_eq!(
+            render_body("{{body.user | default \"none\"}}", "{}"),
+            "none"
+        );
+        assert!(Template::parse("{{arg.name | nope}}").is_err());
+        let root = json!({"arg": {"q": "a\"b"}});
+        assert_eq!(render("{{arg.q | json-escape}}", root.clone()), "a\\\"b");
+        // inside a JSON string the escaping filter isn't escaped again, while
+        // `json` output is text like any other
+        let src = r#"{"x": "{{arg.q | json-escape}}", "y": "{{arg.q | json_escape | upper}}", "z": "{{arg.q | json}}"}"#;
+        assert_eq!(
+            serde_json::from_str::<Value>(&render(src, root)).unwrap(),
+            json!({"x": "a\"b", "y": "A\\\"B", "z": "\"a\\\"b\""})
+        );
+    }
+
+    #[test]
+    fn test_json_escaping() {
+        let root = json!({"arg": {"name": "say \"hi\"\n", "id": "12", "tag": "a,b"}});
+        let src = r#"{"name": "{{arg.name}}", "id": {{arg.id}}, "tag": {{arg.tag}}, "none": {{arg.none}}}"#;
+        let out = render(src, root.clone());
+        let parsed: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            parsed,
+            json!({"name": "say \"hi\"\n", "id": 12, "tag": "a,b", "none": null})
+        );
+        let src = r#"{"raw": "{{{arg.name}}}", "also": "{{& arg.id}}"}"#;
+        assert_eq!(
+            render(src, root.clone()),
+            "{\"raw\": \"say \"hi\"\n\", \"also\": \"12\"}"
+        );
+        assert_eq!(render("plain {{arg.name}}", root), "plain say \"hi\"\n");
+    }
+
+    #[test]
+    fn test_json_escaping_tracks_strings_across_blocks() {
+        let root = json!({"arg": {"q": "\"", "items": ["x\"y"]}});
+        let src =
+            r#"["{{#if arg.q}}{{arg.q}}{{else}}-{{/if}}", {{#each arg.items}}"{{this}}"{{/each}}]"#;
+        let out = render(src, root);
+        assert_eq!(
+            serde_json::from_str::<Value>(&out).unwrap(),
+            json!(["\"", "x\"y"])
+        );
+    }
+
+    #[test]
+    fn test_json_escaping_in_body_starting_with_a_tag() {
+        let root = json!({"arg": {"q": "a\"b"}});
+        let out = render(r#"{{#if arg.q}}{"a": "{{arg.q}}"}{{/if}}"#, root.clone());
+        assert_eq!(
+            serde_json::from_str::<Value>(&out).unwrap(),
+            json!({"a": "a\"b"})
+        );
+        let src = r#"{{! leading comment }} {{#unless arg.none}}[{{arg.q}}]{{/unless}}"#;
+        assert_eq!(
+            serde_json::from_str::<Value>(&render(src, root.clone())).unwrap(),
+            json!(["a\"b"])
+        );
+        assert_eq!(render("{{arg.q}} is plain", root), "a\"b is plain");
+    }
+
+    #[test]
+    fn test_json_body_echo_stays_json() {
+        let src = r#"{"echo": {{body}}, "text": "{{body}}"}"#;
+        let out = render_body(src, r#"{"a": 1}"#);
+        assert_eq!(
+            serde_json::from_str::<Value>(&out).unwrap(),
+            json!({"echo": {"a": 1}, "text": "{\"a\": 1}"})
+        );
+    }
+
+    #[test]
+    fn test_now_helper() {
+        let out = render(
+            "{{now}} {{now unit=\"ms\"}} {{now offset=\"+1h\"}} {{now format=\"%Y\"}}",
+            json!({}),
+        );
+        let parts: Vec<i64> = out.split(' ').map(|p| p.parse().unwrap()).collect();
+        assert!(parts[1] / 1000 - parts[0] <= 1);
+        assert!((3599..=3601).contains(&(parts[2] - parts[0])));
+        assert!(parts[3] >= 2024);
+        assert_eq!(render("{{now offset=\"soon\"}}", json!({})), "");
+        let out = render("{{now offset=\"+15250000w\" format=\"%Y\"}}", json!({}));
+        assert!(out.parse::<i64>().unwrap() > 290_000);
+        assert_eq!(render("{{now offset=\"+15250284000w\"}}", json!({})), "");
+    }
+
+    #[test]
+    fn test_static_text() {
+        let template = Template::parse("{\"ok\": true}{{! note }}").unwrap();
+        assert_eq!(template.static_text().as_deref(), Some("{\"ok\": true}"));
+        assert!(Template::parse("{{path}}").unwrap().static_text().is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("{{#if x}}open").is_err());
+        assert!(Template::parse("{{/if}}").is_err());
+    }
+
+    #[test]
+    fn test_literal_braces() {
+        let root = json!({"path": "/a"});
+        assert_eq!(
+            render("<script>var t='{{';</script>", root.clone()),
+            "<script>var t='{{';</script>"
+        );
+        assert_eq!(render("{{path}} {{path", root.clone()), "/a {{path");
+        assert_eq!(
+            render("<script>var t='{{';</script> {{path}}", root.clone()),
+            "<script>var t='{{';</script> /a"
+        );
+        assert_eq!(render("\\{{path}} {{path}}", root.clone()), "{{path}} /a");
+        assert_eq!(render("x {{{path}} done", root), "x {/a done");
+    }
+
+    #[test]
+    fn test_header_default() {
+        let mut ctx = Context::new(json!({"header": {"x-id": "1"}}));
+        ctx.header_default = Value::String("anon".to_string());
+        let template = Template::parse("{{header.X-Id}} {{header.missing}}").unwrap();
+        assert_eq!(template.render(&ctx, &mut SimpleRNG::new()), "1 anon");
+    }
+}
@@ -16,6 +16,16 @@ impl SimpleRNG {
         SimpleRNG { state: seed }
     }
 
+    pub fn with_seed(seed: u64) -> Self {
+        // splitmix64 so close seeds don't produce similar streams
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        SimpleRNG {
+            state: z ^ (z >> 31),
+        }
+    }
+
     pub fn next(&mut self) -> u64 {
         self.state = self.state.wrapping_mul(6364136223846793061).wrapping_add(1);
         self.state >> 16
@@ -23,114 +33,201 @@ impl SimpleRNG {
 
     pub fn next_range(&mut self, min: u64, max: u64) -> u64 {
         let scaled_range = max - min;
+        if scaled_range == 0 {
+            return min;
+        }
         let scaled_random = self.next() % scaled_range;
         min + scaled_random
     }
-}
 
-pub fn compare_path(path: String, path2: String) -> bool {
-    let parts = path.split("/");
-    let parts2 = path2.split("/");
-    if parts.clone().count() != parts2.clone().count() {
-        return false;
+    // Uniform float in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.next() as f64 / (1u64 << 48) as f64
     }
-    for (part, part2) in parts.zip(parts2) {
-        if part == part2 {
-            continue;
-        } else if part.starts_with("{") && part.ends_with("}") {
-            continue;
-        } else {
-            return false;
+}
+
+// One RNG stream for the whole server, plus one per endpoint that sets its
+// own `seed` so its values don't depend on the rest of the traffic
+pub struct RngPool {
+    global: SimpleRNG,
+    endpoints: HashMap<String, SimpleRNG>,
+}
+
+impl RngPool {
+    pub fn new(seed: Option<u64>) -> Self {
+        RngPool {
+            global: seed
+                .map(SimpleRNG::with_seed)
+                .unwrap_or_else(SimpleRNG::new),
+            endpoints: HashMap::new(),
         }
     }
 
-    return true;
-}
+    // Stream shared by everything without a seed of its own
+    pub fn global(&mut self) -> &mut SimpleRNG {
+        &mut self.global
+    }
 
-pub fn clean_arg(arg_value: String) -> String {
-    let url_decoding_map: HashMap<&str, char> = vec![
-        ("%20", ' '),
-        ("%21", '!'),
-        ("%22", '"'),
-        ("%23", '#'),
-        ("%24", '$'),
-        ("%25", '%'),
-        ("%26", '&'),
-        ("%27", '\''),
-        ("%28", '('),
-        ("%29", ')'),
-        ("%2A", '*'),
-        ("%2B", '+'),
-        ("%2C", ','),
-        ("%2D", '-'),
-        ("%2E", '.'),
-        ("%2F", '/'),
-        ("%3A", ':'),
-        ("%3B", ';'),
-        ("%3C", '<'),
-        ("%3D", '='),
-        ("%3E", '>'),
-        ("%3F", '?'),
-        ("%40", '@'),
-        ("%5B", '['),
-        ("%5C", '\\'),
-        ("%5D", ']'),
-        ("%5E", '^'),
-        ("%5F", '_'),
-        ("%60", '`'),
-        ("%7B", '{'),
-        ("%7C", '|'),
-        ("%7D", '}'),
-        ("%7E", '~'),
-    ]
-    .into_iter()
-    .collect();
-    let mut decoded = arg_value.clone();
-    for item in url_decoding_map.keys() {
-        decoded = decoded.replace(
-            item,
-            url_decoding_map.get(item).unwrap().to_string().as_str(),
-        );
+    // Stream for a single response, seeded from the endpoint's stream or the
+    // global one so the pool is only locked for one draw
+    pub fn fork(&mut self, key: &str, seed: Option<u64>) -> SimpleRNG {
+        let stream = match seed {
+            Some(seed) => self
+                .endpoints
+                .entry(key.to_string())
+                .or_insert_with(|| SimpleRNG::with_seed(seed)),
+            None => self.global(),
+        };
+        SimpleRNG::with_seed(stream.next())
     }
-    return decoded;
 }
 
-pub fn get_path_args(path: String, path2: String) -> Option<HashMap<String, String>> {
-    let parts = path.split("/");
-    let parts2 = path2.split("/");
-    let mut params = HashMap::new();
-    for (part, part2) in parts.zip(parts2) {
-        if part == part2 {
-            continue;
-        } else if part2.starts_with("{") && part2.ends_with("}") {
-            let key = part2
-                .trim_start_matches("{{")
-                .trim_end_matches("}}")
-                .to_string();
-            params.insert(key, part.to_string());
-            continue;
-        } else {
-            return None;
+// Percent-decodes in a single pass, so "%2520" becomes "%20" and not " ".
+// Query args use the form encoding where `+` is a space, paths keep it.
+// Malformed escapes are left as they are and invalid UTF-8 is replaced.
+pub fn percent_decode(value: &str, form: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if form => decoded.push(b' '),
+            byte => decoded.push(byte),
         }
+        i += 1;
     }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
 
-    return Some(params);
+// Query args as sent by hteapot are still encoded, keys included
+pub fn decode_args(args: &HashMap<String, String>) -> HashMap<String, String> {
+    args.iter()
+        .map(|(k, v)| (percent_decode(k, true), percent_decode(v, true)))
+        .collect()
 }
 
 pub fn print_args(args: &HashMap<String, String>) -> String {
-    let mut result = String::new();
-    result.push('?');
-    for (k, v) in args {
-        result.push_str(format!("{}={}&", k, v).as_str());
+    let query = query_string(args);
+    if query.is_empty() {
+        return query;
     }
-    result.pop();
-    return result;
+    format!("?{}", query)
+}
+
+// hteapot only exposes the parsed args, so the original query string can't
+// be recovered byte for byte. Keys are sorted to keep the output stable.
+pub fn query_string(args: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<(&String, &String)> = args.iter().collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+// Converts days since 1970-01-01 into a (year, month, day) civil date
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
 }
 
-pub fn now() -> u64 {
-    let start = SystemTime::now();
-    let since_the_epoch = start
+pub fn parse_body(body: &str) -> Option<serde_json::Value> {
+    serde_json::from_str::<serde_json::Value>(body).ok()
+}
+
+pub fn now_millis() -> i64 {
+    let since_the_epoch = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("time should go forward");
-    since_the_epoch.as_secs()
+    since_the_epoch.as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_string_is_stable() {
+        let args: HashMap<String, String> = vec![
+            ("b".to_string(), "2".to_string()),
+            ("a".to_string(), "1".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(query_string(&args), "a=1&b=2");
+        assert_eq!(print_args(&args), "?a=1&b=2");
+        assert_eq!(print_args(&HashMap::new()), "");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("hello%20world", false), "hello world");
+        assert_eq!(percent_decode("a+b%2Bc", true), "a b+c");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("Jos%C3%A9", false), "José");
+        assert_eq!(percent_decode("%2520", false), "%20");
+        assert_eq!(percent_decode("a%2Fb", false), "a/b");
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%zz%4", false), "%zz%4");
+        assert_eq!(percent_decode("%FF", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut a = SimpleRNG::with_seed(42);
+        let mut b = SimpleRNG::with_seed(42);
+        for _ in 0..10 {
+            assert_eq!(a.next(), b.next());
+        }
+        assert_ne!(
+            SimpleRNG::with_seed(1).next(),
+            SimpleRNG::with_seed(2).next()
+        );
+    }
+
+    #[test]
+    fn test_rng_pool_endpoint_streams() {
+        let mut pool = RngPool::new(Some(7));
+        let first = pool.fork("GET #0 /a", Some(1)).next();
+        pool.fork("GET #1 /b", None).next();
+        let mut other = RngPool::new(Some(7));
+        assert_eq!(other.fork("GET #0 /a", Some(1)).next(), first);
+        assert_eq!(
+            SimpleRNG::with_seed(SimpleRNG::with_seed(7).next()).next(),
+            other.fork("GET #1 /b", None).next()
+        );
+        // same path and method, different endpoints
+        assert_ne!(
+            pool.fork("GET #2 /a", Some(2)).next(),
+            other.fork("GET #3 /a", Some(3)).next()
+        );
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
 }